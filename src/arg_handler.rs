@@ -1,9 +1,25 @@
 extern crate clap;
 
-use self::clap::{Arg, ArgMatches, App};
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter;
+use std::path::Path;
+use std::process;
+
+use self::clap::{Arg, ArgMatches, App, AppSettings, SubCommand};
 
 use app::*;
-use bf::Options;
+use bf::{Dialect, LintCode, Options, Pipeline, ReportFormat};
+
+
+
+/// The names of the options that may be passed, excluding arguments and
+/// subcommands.
+const OPTIONS: &[&str] = &[
+    "buffer", "profiler", "describe", "pretty", "dialect", "debug-char", "inline-input", "inline-only",
+    "opt-level", "strict", "passes", "disable-pass", "eval-budget", "opt-report",
+];
 
 
 
@@ -15,14 +31,52 @@ pub struct ArgHandler<'a> {
 impl<'a: 'b, 'b> ArgHandler<'a> {
 
     /// Parse CLI arguments.
+    ///
+    /// If the program file starts with a shebang line, options following the
+    /// name of this application on that line are used as well.
     pub fn parse() -> ArgHandler<'a> {
+        ArgHandler::parse_from(env::args().collect())
+    }
+
+    /// Parse the given CLI arguments, see `parse`.
+    ///
+    /// Options passed on the command line override the same options on the
+    /// shebang line.
+    fn parse_from(args: Vec<String>) -> ArgHandler<'a> {
         // Handle/parse arguments
-        let matches = App::new(APP_NAME)
+        let mut args = split_shebang_args(args);
+        let mut matches = ArgHandler::app().get_matches_from(&args);
+
+        // Parse again with the options from the program shebang line, that
+        // weren't passed already, such as when running the program as script
+        let options: Vec<String> = matches.value_of("FILE")
+            .map(|file| shebang_options(file, &args[0]))
+            .map(group_options)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|option| !passed(&matches, option))
+            .flatten()
+            .collect();
+        if !options.is_empty() {
+            args.splice(1..1, options);
+            matches = ArgHandler::app().get_matches_from(&args);
+        }
+
+        // Instantiate
+        ArgHandler {
+            matches,
+        }
+    }
+
+    /// Build the CLI argument definitions.
+    fn app() -> App<'a, 'b> {
+        App::new(APP_NAME)
             .version(APP_VERSION)
             .author(APP_AUTHOR)
             .about(APP_ABOUT)
+            .setting(AppSettings::SubcommandsNegateReqs)
             .arg(Arg::with_name("FILE")
-                .help("Brainfuck file to interpret")
+                .help("Brainfuck file to interpret, or - to read from stdin")
                 .required(true)
                 .index(1))
 			.arg(Arg::with_name("buffer")
@@ -43,12 +97,65 @@ impl<'a: 'b, 'b> ArgHandler<'a> {
                 .long("pretty")
                 .alias("prettify")
                 .help("Pretify described program logic"))
-            .get_matches();
-
-        // Instantiate
-        ArgHandler {
-            matches,
-        }
+			.arg(Arg::with_name("dialect")
+                .long("dialect")
+                .value_name("DIALECT")
+                .default_value("brainfuck")
+                .help("Source dialect: brainfuck, ook, blub, spoon or a token map file"))
+			.arg(Arg::with_name("debug-char")
+                .long("debug-char")
+                .help("Dump memory state to stderr on the '#' debug instruction"))
+			.arg(Arg::with_name("inline-input")
+                .short("i")
+                .long("inline-input")
+                .help("Use everything after the first '!' in the program as input"))
+			.arg(Arg::with_name("inline-only")
+                .long("inline-only")
+                .requires("inline-input")
+                .help("Read zero instead of terminal input after the inline input"))
+			.arg(Arg::with_name("opt-level")
+                .short("O")
+                .value_name("LEVEL")
+                .possible_values(&["0", "1", "2", "3"])
+                .default_value("2")
+                .help("Optimization level, 3 optimizes until nothing changes"))
+			.arg(Arg::with_name("strict")
+                .long("strict")
+                .help("Keep loops that never end, instead of optimizing them away"))
+			.arg(Arg::with_name("passes")
+                .long("passes")
+                .value_name("PASSES")
+                .use_delimiter(true)
+                .help("Optimization passes to run in order, separated by commas"))
+			.arg(Arg::with_name("disable-pass")
+                .long("disable-pass")
+                .value_name("PASS")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Disable an optimization pass, such as add_and_zero"))
+			.arg(Arg::with_name("eval-budget")
+                .long("eval-budget")
+                .value_name("STEPS")
+                .validator(|steps| steps.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Maximum number of steps to evaluate the program start for at compile time"))
+			.arg(Arg::with_name("opt-report")
+                .long("opt-report")
+                .value_name("FORMAT")
+                .possible_values(&["human", "json"])
+                .help("Report what each optimization pass did to stderr, as human readable text or JSON"))
+            .subcommand(SubCommand::with_name("lint")
+                .about("Check a brainfuck file for problems, without running it")
+                .arg(Arg::with_name("FILE")
+                    .help("Brainfuck file to check, or - to read from stdin")
+                    .required(true)
+                    .index(1))
+                .arg(Arg::with_name("allow")
+                    .short("A")
+                    .long("allow")
+                    .value_name("LINT")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Suppress the lint with the given code or name, such as W002")))
     }
 
     /// Get the file property.
@@ -77,13 +184,246 @@ impl<'a: 'b, 'b> ArgHandler<'a> {
 		self.matches.is_present("pretty")
 	}
 
+	/// Check whether to interpret the `#` debug instruction.
+	pub fn debug_char(&self) -> bool {
+		self.matches.is_present("debug-char")
+	}
+
+	/// Check whether to use input after the first `!` in the program.
+	pub fn inline_input(&self) -> bool {
+		self.matches.is_present("inline-input")
+	}
+
+	/// Check whether to only read inline input, not falling back to the
+	/// terminal.
+	pub fn inline_only(&self) -> bool {
+		self.matches.is_present("inline-only")
+	}
+
+	/// Get the dialect the program source is written in.
+	///
+	/// If the dialect could not be loaded, an error is reported and the
+	/// application exits.
+	pub fn dialect(&self) -> Dialect {
+		let name = self.matches.value_of("dialect").unwrap();
+		Dialect::from_name(name).unwrap_or_else(|err| {
+			eprintln!("error: {}", err);
+			process::exit(1);
+		})
+	}
+
+	/// Get the optimization pipeline to run on the program.
+	///
+	/// If an unknown optimization pass is given, an error is reported and
+	/// the application exits.
+	pub fn pipeline(&self) -> Pipeline {
+		let level = self.matches.value_of("opt-level").unwrap().parse().unwrap();
+		let pipeline = match self.matches.values_of("passes") {
+			Some(names) => Pipeline::with_passes(&names.collect::<Vec<_>>(), level >= 3),
+			None => Ok(Pipeline::level(level)),
+		};
+		let mut pipeline = pipeline.unwrap_or_else(|err| {
+			eprintln!("error: {}", err);
+			process::exit(1);
+		});
+
+		if self.matches.is_present("strict") {
+			pipeline.strict();
+		}
+
+		if let Some(steps) = self.matches.value_of("eval-budget") {
+			pipeline.budget(steps.parse().unwrap());
+		}
+
+		for name in self.matches.values_of("disable-pass").into_iter().flatten() {
+			pipeline.disable(name).unwrap_or_else(|err| {
+				eprintln!("error: {}", err);
+				process::exit(1);
+			});
+		}
+		pipeline
+	}
+
+	/// Get the format to report what optimization passes did in, if they
+	/// should be reported.
+	pub fn opt_report(&self) -> Option<ReportFormat> {
+		self.matches.value_of("opt-report")
+			.map(|name| ReportFormat::from_name(name).unwrap())
+	}
+
+	/// Get the arguments of the lint subcommand, if it is used.
+	/// The file to check, and the lints to suppress are returned.
+	///
+	/// If an unknown lint is given, an error is reported and the application
+	/// exits.
+	pub fn lint(&'a self) -> Option<(&'b str, Vec<LintCode>)> {
+		let matches = self.matches.subcommand_matches("lint")?;
+		let allowed = matches.values_of("allow")
+			.map(|values| values.collect())
+			.unwrap_or_else(Vec::new)
+			.into_iter()
+			.map(|code| LintCode::find(code).unwrap_or_else(|| {
+				eprintln!("error: unknown lint '{}'", code);
+				process::exit(1);
+			}))
+			.collect();
+		Some((matches.value_of("FILE").unwrap(), allowed))
+	}
+
     /// Create an interpreter options object, based on the CLI arguments.
     pub fn as_options(&self) -> Options {
-        Options::default(
+        let mut options = Options::default(
             self.buffer(),
             self.profile(),
             self.describe(),
             self.pretty(),
-        )
+        );
+        options.dialect = self.dialect();
+        options.debug = self.debug_char();
+        options.inline_input = self.inline_input();
+        options.input_fallback = !self.inline_only();
+        options.pipeline = self.pipeline();
+        options.opt_report = self.opt_report();
+
+        // Inline input can't be used if the dialect uses the separator
+        if options.inline_input && !options.dialect.supports_inline_input() {
+            eprintln!("error: inline input is not supported for the selected dialect");
+            process::exit(1);
+        }
+
+        options
+    }
+}
+
+
+
+/// Split the options passed from a shebang line when running a program as
+/// script. These are passed as single argument, such as `"-b -p"`.
+fn split_shebang_args(mut args: Vec<String>) -> Vec<String> {
+    let split = args.get(1)
+        .filter(|arg| arg.starts_with('-') && arg.contains(char::is_whitespace))
+        .map(|arg| arg.split_whitespace().map(String::from).collect::<Vec<_>>());
+    if let Some(split) = split {
+        args.splice(1..2, split);
     }
+    args
+}
+
+/// Group the given shebang option words into options, each being an option
+/// word followed by its values, such as `--dialect ook`.
+fn group_options(words: Vec<String>) -> Vec<Vec<String>> {
+    let mut options: Vec<Vec<String>> = vec![];
+    for word in words {
+        match options.last_mut() {
+            Some(option) if !word.starts_with('-') => option.push(word),
+            _ => options.push(vec![word]),
+        }
+    }
+    options
+}
+
+/// Check whether any option in the given shebang `option` group was passed
+/// in the command line `matches`.
+///
+/// Groups that don't parse are never passed, so parsing reports them.
+fn passed(matches: &ArgMatches, option: &[String]) -> bool {
+    let args = iter::once(APP_NAME.to_owned())
+        .chain(option.iter().cloned())
+        .chain(iter::once("-".to_owned()));
+    match ArgHandler::app().get_matches_from_safe(args) {
+        Ok(option) => OPTIONS.iter()
+            .any(|name| option.occurrences_of(name) > 0 && matches.occurrences_of(name) > 0),
+        Err(_) => false,
+    }
+}
+
+/// Get the options from the shebang line of the program file at `path`.
+///
+/// These are the words following the one naming this application, or the
+/// given executable `bin`, such as `-b` in `#!/usr/bin/env brainfuck-rs-quick -b`.
+/// If the file doesn't start with a shebang line, nothing is returned.
+fn shebang_options(path: &str, bin: &str) -> Vec<String> {
+    // Read the first line of the program file, not from stdin
+    let mut line = String::new();
+    if path == "-" {
+        return vec![];
+    }
+    let read = File::open(path)
+        .map(BufReader::new)
+        .and_then(|mut reader| reader.read_line(&mut line));
+    if read.is_err() || !line.starts_with("#!") {
+        return vec![];
+    }
+
+    // Take the words after the one naming this application
+    let bin = Path::new(bin).file_name();
+    line[2..].split_whitespace()
+        .skip_while(|word| {
+            let name = Path::new(word).file_name();
+            name != bin && name != Some(APP_NAME.as_ref())
+        })
+        .skip(1)
+        .map(String::from)
+        .collect()
+}
+
+
+
+/// Test whether options passed from a shebang line as single argument are
+/// split.
+#[test]
+fn test_split_shebang_args() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(
+        split_shebang_args(args(&["brainfuck-rs-quick", "-b -O1", "prog.b"])),
+        args(&["brainfuck-rs-quick", "-b", "-O1", "prog.b"]),
+    );
+    for unsplit in &[&["brainfuck-rs-quick", "-b", "a b.b"][..], &["brainfuck-rs-quick", "a b.b"]] {
+        assert_eq!(split_shebang_args(args(unsplit)), args(unsplit));
+    }
+}
+
+/// Test whether options on the shebang line are used, unless the same
+/// options are passed on the command line.
+#[test]
+fn test_shebang_options() {
+    use std::fs;
+
+    let path = env::temp_dir().join(format!("brainfuck-rs-quick-shebang-{}.b", process::id()));
+    fs::write(&path, "#!/usr/bin/env brainfuck-rs-quick -b -p -O1 --dialect ook\n+.").unwrap();
+    let path = path.to_str().unwrap().to_owned();
+    let parse = |args: &[&str]| {
+        let args = iter::once(APP_NAME)
+            .chain(args.iter().cloned())
+            .chain(iter::once(path.as_str()))
+            .map(String::from)
+            .collect();
+        ArgHandler::parse_from(args)
+    };
+
+    assert_eq!(
+        shebang_options(&path, APP_NAME),
+        vec!["-b", "-p", "-O1", "--dialect", "ook"],
+    );
+    assert_eq!(
+        group_options(shebang_options(&path, APP_NAME)),
+        vec![vec!["-b"], vec!["-p"], vec!["-O1"], vec!["--dialect", "ook"]],
+    );
+
+    let args = parse(&[]);
+    assert!(args.buffer() && args.profile());
+    assert_eq!(args.matches.value_of("opt-level"), Some("1"));
+    assert_eq!(args.matches.value_of("dialect"), Some("ook"));
+
+    let args = parse(&["-p", "-O3", "--dialect=brainfuck"]);
+    assert!(args.buffer() && args.profile());
+    assert_eq!(args.matches.value_of("opt-level"), Some("3"));
+    assert_eq!(args.matches.value_of("dialect"), Some("brainfuck"));
+
+    // Running the program as script passes the shebang options as well
+    let args = parse(&["-b -p -O1 --dialect ook"]);
+    assert!(args.buffer() && args.profile());
+
+    fs::remove_file(&path).unwrap();
 }
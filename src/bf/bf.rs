@@ -1,23 +1,65 @@
+use std::io::{BufReader, Read};
 use std::u8;
 
 use profiler::Profiler;
 
-use super::{Interpreter, Memory, Options};
+use super::{Error, Input, Interpreter, Memory, Op, Options, Span};
 
 
 
-/// Interpret a Brainfuck program from a string.
+/// Interpret a Brainfuck program from the given reader.
 /// Return the result string.
-pub fn bf(prog: &str, options: &Options) -> String {
+///
+/// The program is streamed byte by byte from the reader while it is
+/// interpreted, through a buffer.
+/// If inline input is enabled in the options, everything after the first `!`
+/// in the program is used as program input.
+///
+/// An error is returned if the program could not be read or parsed, or if it
+/// hangs in a loop that never ends.
+pub fn bf<R: Read>(prog: &mut R, options: &Options) -> Result<String, Error> {
     // Create application memory, and define an output vector
     let mut memory = Memory::new();
     let mut output: Vec<u8> = vec![];
 
-    // Interpret the program
-    let start = Interpreter::interpret(&mut prog.bytes(), &options);
+    // Interpret the program, stop at the first read error and report it
+    let mut prog = BufReader::new(prog);
+    let mut failed = None;
+    let start = Interpreter::interpret(
+        prog.by_ref().bytes().map_while(|byte| byte.map_err(|err| failed = Some(err)).ok()),
+        options,
+    );
+    if let Some(err) = failed {
+        return Err(err.into());
+    }
+    let mut start = start?;
+
+    // Optimize the program
+    let mut profiler = Profiler::new(options.profile);
+    let changes = match options.opt_report {
+        Some(format) => {
+            let report = options.pipeline.report(&mut start);
+            eprint!("{}", report.render(format));
+            report.passes.iter().map(|pass| (pass.name, pass.changes)).collect()
+        },
+        None => options.pipeline.run(&mut start),
+    };
+    if options.profile {
+        profiler.report("Optimizing");
+    }
+
+    // Take the remaining bytes as inline input
+    let mut inline = vec![];
+    if options.inline_input {
+        prog.read_to_end(&mut inline)?;
+    }
+    let mut input = Input::new(inline, options.input_fallback);
 
     // Describe program logic
     if options.describe {
+        for line in summary(&start, &changes) {
+            println!("{}", line);
+        }
         if options.pretty {
             println!("{:#?}", start);
         } else {
@@ -27,13 +69,48 @@ pub fn bf(prog: &str, options: &Options) -> String {
 
     // Execute the program from the start and profile
     let mut profiler = Profiler::new(options.profile);
-    start.execute(&mut memory, &options, &mut output);
+    let result = start.execute(&mut memory, options, &mut input, &mut output);
     if options.profile {
         profiler.report("Executing");
     }
+    result?;
 
     // Parse and output the string
-    String::from_utf8(output).unwrap()
+    Ok(String::from_utf8(output).unwrap())
+}
+
+/// Summarize the given optimized program when describing it, given the
+/// number of `changes` each optimization pass made.
+/// The lines of the summary are returned.
+fn summary(program: &Op, changes: &[(&'static str, usize)]) -> Vec<String> {
+    let mut lines = vec![
+        format!("# Interpreted {} bytes of program source", program.span().end.offset),
+    ];
+    if let Some(span) = folded(program) {
+        lines.push(format!(
+            "# Folded {} bytes of program source by evaluating the program start",
+            span.end.offset - span.start.offset,
+        ));
+    }
+    if let Some(&(_, count)) = changes.iter().find(|&&(pass, _)| pass == "dead_loops") {
+        lines.push(format!("# Removed {} dead loops", count));
+    }
+    for &(pass, count) in changes {
+        lines.push(format!("# Optimization pass {}: {} changes", pass, count));
+    }
+    lines
+}
+
+/// Get the span of program source that was folded by partial evaluation in
+/// the given program, if any.
+fn folded(program: &Op) -> Option<Span> {
+    match *program {
+        Op::Routine(ref ops, false, _) => ops.iter()
+            .take_while(|op| matches!(**op, Op::Print(..) | Op::Load(..)))
+            .map(Op::span)
+            .fold(None, |folded, span| Some(folded.map_or(span, |folded: Span| folded.join(span)))),
+        _ => None,
+    }
 }
 
 
@@ -43,7 +120,190 @@ pub fn bf(prog: &str, options: &Options) -> String {
 #[test]
 fn test_hello_world() {
     assert_eq!(
-        bf("++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.+++++++..+++.>++.<<+++++++++++++++.>.+++.------.--------.>+.>."),
+        bf(
+            &mut "++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.+++++++..+++.>++.<<+++++++++++++++.>.+++.------.--------.>+.>.".as_bytes(),
+            &Options::default(true, false, false, false),
+        ).unwrap(),
         "Hello World!\n",
     );
 }
+
+/// Test whether unbalanced brackets are reported at the offending position.
+#[test]
+fn test_unbalanced_brackets() {
+    use super::ParseError;
+
+    let options = Options::default(true, false, false, false);
+    let parse_err = |prog: &str| match bf(&mut prog.as_bytes(), &options) {
+        Err(Error::Parse(err)) => err,
+        _ => panic!("expected a parse error"),
+    };
+
+    let err = parse_err("+[-]\n+]");
+    assert_eq!(err.bracket(), ']');
+    assert_eq!((err.position().line, err.position().column), (2, 2));
+
+    let err = parse_err("+[-\n[-]");
+    assert_eq!(err.bracket(), '[');
+    assert_eq!((err.position().line, err.position().column), (1, 2));
+    match err {
+        ParseError::Unclosed(_, end) => assert_eq!((end.line, end.column), (2, 4)),
+        _ => panic!("expected an unclosed loop error"),
+    }
+}
+
+/// Test whether operations keep the span of program source they were
+/// translated from, also after folding and optimizing.
+#[test]
+fn test_op_spans() {
+    use super::{Op, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.pipeline = Pipeline::level(1);
+    let mut start = Interpreter::interpret("+ +>\n[-]>".bytes(), &options).unwrap();
+    options.pipeline.run(&mut start);
+
+    let ops = match start {
+        Op::Routine(ref ops, false, _) => ops,
+        _ => panic!("expected a top level routine"),
+    };
+    let spans: Vec<_> = ops.iter()
+        .map(|op| {
+            let span = op.span();
+            (span.start.offset, span.end.offset, span.start.line, span.start.column)
+        })
+        .collect();
+    assert_eq!(spans, vec![(0, 3, 1, 1), (3, 4, 1, 4), (5, 8, 2, 1), (8, 9, 2, 4)]);
+}
+
+/// Test whether the summary of described programs reports what was
+/// optimized.
+#[test]
+fn test_summary() {
+    use super::Pipeline;
+
+    let mut options = Options::default(true, false, false, false);
+    let mut program = Interpreter::interpret("[comment.]+[-][more, comment]".bytes(), &options).unwrap();
+    let changes = options.pipeline.run(&mut program);
+    let lines = summary(&program, &changes);
+    assert_eq!(lines[0], "# Interpreted 29 bytes of program source");
+    assert!(lines.contains(&"# Removed 2 dead loops".into()), "{:?}", lines);
+
+    // Dead loops are only reported if the pass runs
+    options.pipeline = Pipeline::level(1);
+    let mut program = Interpreter::interpret("[comment.]+".bytes(), &options).unwrap();
+    let changes = options.pipeline.run(&mut program);
+    assert!(summary(&program, &changes).iter().all(|line| !line.contains("dead loops")));
+}
+
+/// Test whether the debug instruction is only interpreted when enabled,
+/// and whether it is never folded into other operations.
+#[test]
+fn test_debug_char() {
+    use super::{Op, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.pipeline = Pipeline::level(1);
+    let count = |options: &Options, prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), options).unwrap();
+        options.pipeline.run(&mut program);
+        match program {
+            Op::Routine(ref ops, false, _) => ops.len(),
+            _ => panic!("expected a top level routine"),
+        }
+    };
+    assert_eq!(count(&options, "+#+[-#]"), 2);
+
+    options.debug = true;
+    assert_eq!(count(&options, "+#+[-#]"), 4);
+    assert_eq!(bf(&mut "++#.".as_bytes(), &options).unwrap(), "\u{2}");
+}
+
+/// Test whether input after the first `!` in a program is used as input.
+#[test]
+fn test_inline_input() {
+    let mut options = Options::default(true, false, false, false);
+    options.inline_input = true;
+    options.input_fallback = false;
+
+    assert_eq!(bf(&mut ",+.,+.,.!ab".as_bytes(), &options).unwrap(), "bc\u{0}");
+    assert_eq!(bf(&mut ",.!!x".as_bytes(), &options).unwrap(), "!");
+}
+
+/// Test whether program source is read from any reader, and whether read
+/// errors are reported.
+#[test]
+fn test_read_source() {
+    use std::io;
+
+    /// A reader failing after the given bytes.
+    struct Failing<'a>(&'a [u8]);
+
+    impl<'a> Read for Failing<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    // Comments aren't required to be valid UTF-8
+    let options = Options::default(true, false, false, false);
+    let prog: &[u8] = b"\xff+++\xc3(comment \xfe\xfe)[\xe2>++<-]>.";
+    assert_eq!(bf(&mut &prog[..], &options).unwrap(), "\u{6}");
+
+    assert_eq!(bf(&mut Failing(b"+[>+"), &options), Err(Error::Io(io::ErrorKind::BrokenPipe)));
+}
+
+/// Test whether accessing memory past the end of it is reported, at every
+/// optimization level.
+#[test]
+fn test_out_of_memory() {
+    use super::{Pipeline, RuntimeError};
+
+    let mut options = Options::default(true, false, false, false);
+    for level in 0..4 {
+        options.pipeline = Pipeline::level(level);
+        for prog in &["+[>+]", "+[[>]+]", "+[->+>[-]<<[->+<]>]"] {
+            match bf(&mut prog.as_bytes(), &options) {
+                Err(Error::Runtime(RuntimeError::OutOfMemory(_))) => {},
+                result => panic!("expected to run out of memory: {} at level {}: {:?}", prog, level, result),
+            }
+        }
+    }
+}
+
+/// Test whether a shebang line at the start of a program is skipped.
+#[test]
+fn test_shebang() {
+    use super::ParseError;
+
+    let options = Options::default(true, false, false, false);
+    assert_eq!(
+        bf(&mut "#!/usr/bin/env brainfuck-rs-quick -b\n++++++++[>++++++++<-]>+.".as_bytes(), &options).unwrap(),
+        "A",
+    );
+    match bf(&mut "#!/usr/bin/env brainfuck-rs-quick\n]".as_bytes(), &options) {
+        Err(Error::Parse(ParseError::UnmatchedClose(position))) => assert_eq!(position.line, 2),
+        _ => panic!("expected unmatched close error"),
+    }
+}
+
+/// Test whether deeply nested programs can be interpreted, described and
+/// executed without overflowing the stack.
+#[test]
+fn test_deeply_nested() {
+    let depth = 100_000;
+    let prog = format!("+{}-{}+++.", "[".repeat(depth), "]".repeat(depth));
+    let options = Options::default(true, false, false, false);
+    assert_eq!(bf(&mut prog.as_bytes(), &options).unwrap(), "\u{3}");
+
+    let program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+    assert!(format!("{:?}", program).starts_with("Routine([Inc(1, 0, 1:1..1:2), Routine([Routine(["));
+
+    // Pretty printing indents each level, so keep the output small
+    let prog = format!("+{}-{}", "[".repeat(1000), "]".repeat(1000));
+    let program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+    assert!(format!("{:#?}", program).starts_with("Routine(\n    [\n        Inc(\n            1,\n"));
+}
@@ -0,0 +1,43 @@
+//! # Brainfuck dialect
+//! The plain brainfuck dialect, using the eight canonical characters
+//! `><+-.,[]` as tokens, and `#` as debug token.
+//!
+//! All other characters are considered to be comments, and are skipped.
+
+
+
+use super::{Lexer, Token};
+use super::super::{Position, Source, Span};
+
+
+
+/// Lexer for plain brainfuck.
+pub struct Brainfuck<I> {
+    /// The program source.
+    source: Source<I>,
+}
+
+impl<I: Iterator<Item = u8>> Brainfuck<I> {
+    /// Create a new lexer reading from the given `source`.
+    pub fn new(source: Source<I>) -> Brainfuck<I> {
+        Brainfuck {
+            source,
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Lexer for Brainfuck<I> {
+    fn next_token(&mut self) -> Option<(Token, Span)> {
+        // Skip all bytes that aren't a token
+        loop {
+            let (byte, span) = self.source.next()?;
+            if let Some(token) = Token::from_byte(byte) {
+                return Some((token, span));
+            }
+        }
+    }
+
+    fn current(&self) -> Position {
+        self.source.current()
+    }
+}
@@ -0,0 +1,196 @@
+mod brainfuck;
+mod token_map;
+mod word_pair;
+
+use std::fs::File;
+use std::io::Read;
+
+use super::{Position, Source, Span};
+
+// Reexport
+pub use self::brainfuck::Brainfuck;
+pub use self::token_map::TokenMap;
+pub use self::word_pair::WordPair;
+
+
+
+/// A canonical brainfuck token.
+///
+/// Every dialect translates its program source into these tokens,
+/// which are then interpreted into operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// Seek up, `>`.
+    Right,
+
+    /// Seek down, `<`.
+    Left,
+
+    /// Increase memory cell value, `+`.
+    Inc,
+
+    /// Decrease memory cell value, `-`.
+    Dec,
+
+    /// Output the value of the current memory cell, `.`.
+    Output,
+
+    /// Read user input, `,`.
+    Input,
+
+    /// Start a conditional loop, `[`.
+    Open,
+
+    /// End a conditional loop, `]`.
+    Close,
+
+    /// Dump the memory state for debugging, `#`.
+    /// This token is ignored unless debugging is enabled.
+    Debug,
+}
+
+impl Token {
+    /// Get the token for the given canonical brainfuck character.
+    /// `None` is returned if the character isn't a token.
+    pub fn from_byte(byte: u8) -> Option<Token> {
+        match byte {
+            b'>' => Some(Token::Right),
+            b'<' => Some(Token::Left),
+            b'+' => Some(Token::Inc),
+            b'-' => Some(Token::Dec),
+            b'.' => Some(Token::Output),
+            b',' => Some(Token::Input),
+            b'[' => Some(Token::Open),
+            b']' => Some(Token::Close),
+            b'#' => Some(Token::Debug),
+            _ => None,
+        }
+    }
+}
+
+
+
+/// A lexer, that translates a program source into canonical tokens.
+pub trait Lexer {
+    /// Read the next token from the program source, along with the span it
+    /// was read from.
+    /// `None` is returned if the program source is exhausted.
+    fn next_token(&mut self) -> Option<(Token, Span)>;
+
+    /// Get the position of the next byte in the program source.
+    /// If the source is exhausted, this is the end position of the source.
+    fn current(&self) -> Position;
+}
+
+
+
+/// A brainfuck dialect, defining how program source is tokenized.
+pub enum Dialect {
+    /// Plain brainfuck.
+    Brainfuck,
+
+    /// Ook!, using `Ook.`, `Ook?` and `Ook!` word pairs.
+    Ook,
+
+    /// Blub, using `Blub.`, `Blub?` and `Blub!` word pairs.
+    Blub,
+
+    /// Spoon, using a binary prefix code.
+    Spoon,
+
+    /// A user defined token map.
+    Custom(TokenMap),
+}
+
+impl Dialect {
+    /// Get the dialect for the given `name`.
+    ///
+    /// If the name isn't a built-in dialect, it is used as path to a token
+    /// map file to load. See `TokenMap::parse` for the file format.
+    pub fn from_name(name: &str) -> Result<Dialect, String> {
+        match name.to_lowercase().as_str() {
+            "brainfuck" | "bf" => return Ok(Dialect::Brainfuck),
+            "ook" | "ook!" => return Ok(Dialect::Ook),
+            "blub" => return Ok(Dialect::Blub),
+            "spoon" => return Ok(Dialect::Spoon),
+            _ => {},
+        }
+
+        // Load a token map file
+        let mut map = String::new();
+        File::open(name)
+            .and_then(|mut file| file.read_to_string(&mut map))
+            .map_err(|err| format!("failed to load dialect token map '{}': {}", name, err))?;
+        TokenMap::parse(&map)
+            .map(Dialect::Custom)
+            .map_err(|err| format!("invalid dialect token map '{}': {}", name, err))
+    }
+
+    /// Check whether this dialect supports inline program input after a `!`
+    /// separator. This isn't the case if the dialect uses `!` in its tokens.
+    pub fn supports_inline_input(&self) -> bool {
+        match *self {
+            Dialect::Brainfuck | Dialect::Spoon => true,
+            Dialect::Ook | Dialect::Blub => false,
+            Dialect::Custom(ref map) => !map.uses(b'!'),
+        }
+    }
+
+    /// Create a lexer for this dialect, reading from the given `source`.
+    pub fn lexer<'a, I>(&'a self, source: Source<I>) -> Box<dyn Lexer + 'a>
+        where I: Iterator<Item = u8> + 'a
+    {
+        match *self {
+            Dialect::Brainfuck => Box::new(Brainfuck::new(source)),
+            Dialect::Ook => Box::new(WordPair::new(source, b"Ook")),
+            Dialect::Blub => Box::new(WordPair::new(source, b"Blub")),
+            Dialect::Spoon => Box::new(TokenMap::spoon().lexer(source)),
+            Dialect::Custom(ref map) => Box::new(map.lexer(source)),
+        }
+    }
+}
+
+
+
+/// Collect all tokens from the given program, in the given dialect.
+#[cfg(test)]
+fn tokens(dialect: &Dialect, program: &str) -> Vec<Token> {
+    let mut lexer = dialect.lexer(Source::new(program.bytes(), false));
+    let mut tokens = vec![];
+    while let Some((token, _)) = lexer.next_token() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Test whether the built-in dialects translate to the same tokens.
+#[test]
+fn test_dialects() {
+    let expected = tokens(&Dialect::Brainfuck, "+[->,<].");
+    assert_eq!(expected.len(), 8);
+
+    assert_eq!(
+        tokens(
+            &Dialect::Ook,
+            "Ook. Ook. Ook! Ook? Ook! Ook!\nOok. Ook? Ook. Ook! Ook? Ook.\nOok? Ook! Ook! Ook.",
+        ),
+        expected,
+    );
+    assert_eq!(
+        tokens(
+            &Dialect::Blub,
+            "Blub. Blub. Blub! Blub? Blub! Blub! Blub. Blub? Blub. Blub! Blub? Blub. Blub? Blub! Blub! Blub.",
+        ),
+        expected,
+    );
+    assert_eq!(
+        tokens(&Dialect::Spoon, "1 00100 000 010\n0010110 011 0011 001010"),
+        expected,
+    );
+
+    let map = TokenMap::parse("+ inc\n- dec\n> right\n< left\n. out\n, in\n[ loop\n] pool\n").unwrap();
+    assert_eq!(
+        tokens(&Dialect::Custom(map), "inc loop dec right in left pool out"),
+        expected,
+    );
+}
@@ -0,0 +1,201 @@
+//! # Token map dialects
+//! Trivial substitution dialects, where each canonical token is replaced by
+//! a fixed string. The Spoon dialect and user defined dialects are based on
+//! this.
+//!
+//! The program source is matched greedily, preferring the longest token
+//! string. Bytes that aren't used in any token string are ignored, other
+//! bytes that don't start a token are skipped.
+//!
+//! # Token map files
+//! User defined dialects are loaded from a token map file.
+//! Each line maps a canonical token to it's string, separated by whitespace.
+//! The `#` debug token may be mapped as well.
+//! Empty lines, and lines starting with `;` are ignored.
+//!
+//! ```text
+//! ; A silly dialect
+//! > right
+//! < left
+//! + up
+//! - down
+//! . say
+//! , ask
+//! [ loop
+//! ] pool
+//! ```
+
+
+
+use std::collections::VecDeque;
+
+use super::{Lexer, Token};
+use super::super::{Position, Source, Span};
+
+
+
+/// A map of token strings, defining a substitution dialect.
+#[derive(Clone)]
+pub struct TokenMap {
+    /// The token strings, and the tokens they map to.
+    entries: Vec<(Vec<u8>, Token)>,
+
+    /// Whether a byte is used in any token string, indexed by byte value.
+    alphabet: Vec<bool>,
+
+    /// The length of the longest token string.
+    longest: usize,
+}
+
+impl TokenMap {
+    /// Create a token map from the given token strings.
+    pub fn new(entries: Vec<(Vec<u8>, Token)>) -> TokenMap {
+        let mut alphabet = vec![false; 256];
+        entries.iter()
+            .flat_map(|(text, _)| text)
+            .for_each(|&byte| alphabet[byte as usize] = true);
+        let longest = entries.iter()
+            .map(|(text, _)| text.len())
+            .max()
+            .unwrap_or(0);
+
+        TokenMap {
+            entries,
+            alphabet,
+            longest,
+        }
+    }
+
+    /// Create the token map for the Spoon dialect.
+    pub fn spoon() -> TokenMap {
+        TokenMap::new(vec![
+            (b"1".to_vec(), Token::Inc),
+            (b"000".to_vec(), Token::Dec),
+            (b"010".to_vec(), Token::Right),
+            (b"011".to_vec(), Token::Left),
+            (b"00100".to_vec(), Token::Open),
+            (b"0011".to_vec(), Token::Close),
+            (b"001010".to_vec(), Token::Output),
+            (b"0010110".to_vec(), Token::Input),
+            (b"00101110".to_vec(), Token::Debug),
+        ])
+    }
+
+    /// Parse a token map from the contents of a token map file.
+    ///
+    /// An error message is returned if the map is invalid.
+    pub fn parse(map: &str) -> Result<TokenMap, String> {
+        let mut entries: Vec<(Vec<u8>, Token)> = vec![];
+
+        for (number, line) in map.lines().enumerate() {
+            // Skip empty lines and comments
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            // Parse the token and it's string
+            let token = Token::from_byte(line.as_bytes()[0])
+                .ok_or_else(|| format!(
+                    "line {}: expected one of '><+-.,[]#', found '{}'",
+                    number + 1,
+                    line.chars().next().unwrap(),
+                ))?;
+            let text = line[1..].trim();
+            if text.is_empty() {
+                return Err(format!("line {}: missing token string", number + 1));
+            }
+            if entries.iter().any(|(other, _)| other.as_slice() == text.as_bytes()) {
+                return Err(format!("line {}: duplicate token string '{}'", number + 1, text));
+            }
+
+            entries.push((text.as_bytes().to_vec(), token));
+        }
+
+        if entries.is_empty() {
+            return Err("no tokens defined".into());
+        }
+
+        Ok(TokenMap::new(entries))
+    }
+
+    /// Check whether the given `byte` is used in any token string.
+    pub fn uses(&self, byte: u8) -> bool {
+        self.alphabet[byte as usize]
+    }
+
+    /// Create a lexer for this token map, reading from the given `source`.
+    pub fn lexer<I>(&self, source: Source<I>) -> TokenMapLexer<I>
+        where I: Iterator<Item = u8>
+    {
+        TokenMapLexer {
+            source,
+            map: self.clone(),
+            buffer: VecDeque::with_capacity(self.longest),
+        }
+    }
+
+    /// Find the longest token string the given bytes start with.
+    /// The length of the string and it's token are returned.
+    fn find(&self, bytes: &VecDeque<(u8, Span)>) -> Option<(usize, Token)> {
+        self.entries.iter()
+            .filter(|(text, _)|
+                text.len() <= bytes.len()
+                    && text.iter().zip(bytes).all(|(&a, &(b, _))| a == b)
+            )
+            .max_by_key(|(text, _)| text.len())
+            .map(|(text, token)| (text.len(), *token))
+    }
+}
+
+
+
+/// Lexer for a token map dialect.
+pub struct TokenMapLexer<I> {
+    /// The program source.
+    source: Source<I>,
+
+    /// The token map to use.
+    map: TokenMap,
+
+    /// A buffer of upcoming bytes, used to match token strings.
+    buffer: VecDeque<(u8, Span)>,
+}
+
+impl<I: Iterator<Item = u8>> Lexer for TokenMapLexer<I> {
+    fn next_token(&mut self) -> Option<(Token, Span)> {
+        loop {
+            // Fill the buffer with bytes used in token strings
+            while self.buffer.len() < self.map.longest {
+                match self.source.next() {
+                    Some((byte, span)) =>
+                        if self.map.uses(byte) {
+                            self.buffer.push_back((byte, span));
+                        },
+                    None => break,
+                }
+            }
+            if self.buffer.is_empty() {
+                return None;
+            }
+
+            // Take the longest matching token, or skip a byte
+            match self.map.find(&self.buffer) {
+                Some((len, token)) => {
+                    let span = self.buffer[0].1.join(self.buffer[len - 1].1);
+                    self.buffer.drain(..len);
+                    return Some((token, span));
+                },
+                None => {
+                    self.buffer.pop_front();
+                },
+            }
+        }
+    }
+
+    fn current(&self) -> Position {
+        self.buffer.front()
+            .map(|&(_, span)| span.start)
+            .unwrap_or_else(|| self.source.current())
+    }
+}
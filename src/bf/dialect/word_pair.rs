@@ -0,0 +1,103 @@
+//! # Word pair dialects
+//! Dialects such as Ook! and Blub, that use pairs of words as tokens.
+//!
+//! Each word consists of a fixed base word, followed by a `.`, `?` or `!`
+//! punctuation mark. Two subsequent words form a token, based on their
+//! punctuation marks. Words must be separated by whitespace, other words are
+//! considered to be comments and are skipped.
+//!
+//! # Token pairs
+//! - `. ?`: `>`
+//! - `? .`: `<`
+//! - `. .`: `+`
+//! - `! !`: `-`
+//! - `! .`: `.`
+//! - `. !`: `,`
+//! - `! ?`: `[`
+//! - `? !`: `]`
+
+
+
+use super::{Lexer, Token};
+use super::super::{Position, Source, Span};
+
+
+
+/// Lexer for word pair dialects.
+pub struct WordPair<I> {
+    /// The program source.
+    source: Source<I>,
+
+    /// The base word of the dialect, such as `Ook`.
+    word: &'static [u8],
+}
+
+impl<I: Iterator<Item = u8>> WordPair<I> {
+    /// Create a new lexer reading from the given `source`, using `word` as
+    /// base word.
+    pub fn new(source: Source<I>, word: &'static [u8]) -> WordPair<I> {
+        WordPair {
+            source,
+            word,
+        }
+    }
+
+    /// Read the next dialect word from the source, skipping other words.
+    /// The punctuation mark of the word is returned along with it's span.
+    fn next_word(&mut self) -> Option<(u8, Span)> {
+        loop {
+            // Skip whitespace, find the start of the word
+            let (mut byte, mut span) = self.source.next()?;
+            while (byte as char).is_whitespace() {
+                let next = self.source.next()?;
+                byte = next.0;
+                span = next.1;
+            }
+
+            // Collect the word until the next whitespace
+            let mut word = vec![byte];
+            for (byte, next) in self.source.by_ref() {
+                if (byte as char).is_whitespace() {
+                    break;
+                }
+                word.push(byte);
+                span = span.join(next);
+            }
+
+            // Return the punctuation mark if this is a dialect word
+            if let Some((&mark, base)) = word.split_last() {
+                if base == self.word && (mark == b'.' || mark == b'?' || mark == b'!') {
+                    return Some((mark, span));
+                }
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Lexer for WordPair<I> {
+    fn next_token(&mut self) -> Option<(Token, Span)> {
+        loop {
+            // Read a pair of words
+            let (first, first_span) = self.next_word()?;
+            let (second, second_span) = self.next_word()?;
+
+            // Translate the pair into a token, skip unknown pairs
+            let token = match (first, second) {
+                (b'.', b'?') => Token::Right,
+                (b'?', b'.') => Token::Left,
+                (b'.', b'.') => Token::Inc,
+                (b'!', b'!') => Token::Dec,
+                (b'!', b'.') => Token::Output,
+                (b'.', b'!') => Token::Input,
+                (b'!', b'?') => Token::Open,
+                (b'?', b'!') => Token::Close,
+                _ => continue,
+            };
+            return Some((token, first_span.join(second_span)));
+        }
+    }
+
+    fn current(&self) -> Position {
+        self.source.current()
+    }
+}
@@ -0,0 +1,163 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use super::Position;
+
+
+
+/// An error that occurred while parsing a brainfuck program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// A loop-end `]` was found that has no matching loop-start.
+    ///
+    /// The property defines the position of the stray bracket.
+    UnmatchedClose(Position),
+
+    /// A loop-start `[` was never closed.
+    ///
+    /// The first property defines the position of the unclosed bracket.
+    /// The second property defines the end position of the program, where
+    /// the matching loop-end was expected.
+    Unclosed(Position, Position),
+}
+
+impl ParseError {
+    /// Get the position of the offending bracket.
+    pub fn position(&self) -> Position {
+        match *self {
+            ParseError::UnmatchedClose(position) => position,
+            ParseError::Unclosed(open, _) => open,
+        }
+    }
+
+    /// Get the offending bracket character.
+    pub fn bracket(&self) -> char {
+        match *self {
+            ParseError::UnmatchedClose(_) => ']',
+            ParseError::Unclosed(..) => '[',
+        }
+    }
+
+    /// Get a short message describing this error, without position.
+    pub fn message(&self) -> String {
+        match *self {
+            ParseError::UnmatchedClose(_) =>
+                format!("unmatched '{}', no loop to close", self.bracket()),
+            ParseError::Unclosed(..) =>
+                format!("unclosed '{}', loop never ends", self.bracket()),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let position = self.position();
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message(),
+            position.line,
+            position.column,
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
+
+
+/// An error that occurred while executing a brainfuck program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// The program entered a loop that never ends.
+    ///
+    /// The property defines the position of the loop-start.
+    Hang(Position),
+
+    /// The program accessed a memory cell past the end of memory.
+    ///
+    /// The property defines the position of the operation accessing it.
+    OutOfMemory(Position),
+}
+
+impl RuntimeError {
+    /// Get the position of the offending program source.
+    pub fn position(&self) -> Position {
+        match *self {
+            RuntimeError::Hang(position) | RuntimeError::OutOfMemory(position) => position,
+        }
+    }
+
+    /// Get a short message describing this error, without position.
+    pub fn message(&self) -> String {
+        match *self {
+            RuntimeError::Hang(_) =>
+                "program hangs, entered a loop that never ends".into(),
+            RuntimeError::OutOfMemory(_) =>
+                "memory pointer moved past the end of memory".into(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let position = self.position();
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message(),
+            position.line,
+            position.column,
+        )
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+
+
+/// An error that occurred while running a brainfuck program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The program could not be parsed.
+    Parse(ParseError),
+
+    /// The program failed while executing.
+    Runtime(RuntimeError),
+
+    /// The program source could not be read.
+    ///
+    /// The property defines the kind of the read error.
+    Io(io::ErrorKind),
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Error {
+        Error::Runtime(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err.kind())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref err) => err.fmt(f),
+            Error::Runtime(ref err) => err.fmt(f),
+            Error::Io(kind) => write!(f, "failed to read program: {}", kind),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,53 @@
+use super::tty_read::TermReader;
+
+use super::Options;
+
+
+
+/// The input of a brainfuck program, read by input operations.
+///
+/// Input is first read from inline input, given along with the program.
+/// When that is exhausted, input is read from the terminal if falling back
+/// is enabled. Otherwise zero is read.
+pub struct Input {
+    /// The inline program input.
+    inline: Vec<u8>,
+
+    /// The index of the next inline input byte to read.
+    index: usize,
+
+    /// Whether to fall back to the terminal when inline input is exhausted.
+    fallback: bool,
+}
+
+impl Input {
+    /// Create a new program input with the given `inline` input.
+    ///
+    /// If `fallback` is `true`, input is read from the terminal when the
+    /// inline input is exhausted.
+    pub fn new(inline: Vec<u8>, fallback: bool) -> Input {
+        Input {
+            inline,
+            index: 0,
+            fallback,
+        }
+    }
+
+    /// Read the next input byte.
+    pub fn read(&mut self, options: &Options) -> u8 {
+        // Read from inline input first
+        if let Some(&byte) = self.inline.get(self.index) {
+            self.index += 1;
+            return byte;
+        }
+
+        // Fall back to the terminal, or read zero
+        if !self.fallback {
+            return 0;
+        }
+        TermReader::open_stdin(&options.reader_options)
+            .expect("failed to open user input reader")
+            .read_byte()
+            .expect("failed to read user input")
+    }
+}
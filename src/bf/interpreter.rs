@@ -1,146 +1,154 @@
 use std::mem;
-use std::str::Bytes;
 
 use profiler::Profiler;
 
-use super::optimize::optimize_routine;
-
-use super::{Op, Options};
+use super::dialect::{Lexer, Token};
+use super::{Op, Options, ParseError, Position, Source, Span};
 
 
 
 /// Brainfuck interpreter.
 ///
 /// This interpreter translates a stream of brainfuck program bytes into
-/// operations.
+/// operations. The bytes are tokenized using the dialect from the options.
 pub struct Interpreter;
 
 impl Interpreter {
     /// Interpret a brainfuck program from the given byte stream.
     /// Output a routine containing the whole state.
-    pub fn interpret(program: &mut Bytes, options: &Options) -> Op {
+    ///
+    /// The stream is consumed as the program is interpreted, the program
+    /// doesn't have to be valid UTF-8.
+    ///
+    /// If inline input is enabled in the options, interpreting stops at the
+    /// first `!` byte. The program input following it is left in the stream.
+    ///
+    /// The program isn't optimized, see `Pipeline` for optimizing it.
+    ///
+    /// An error is returned if the program contains unbalanced brackets.
+    pub fn interpret<I>(program: I, options: &Options) -> Result<Op, ParseError>
+        where I: Iterator<Item = u8>
+    {
         // Start a profiler
         let mut profiler = Profiler::new(options.profile);
 
         // Interpret
-        let start = Interpreter::interpret_routine(program, false);
+        let mut lexer = options.dialect.lexer(Source::new(program, options.inline_input));
+        let start = Interpreter::interpret_tokens(&mut *lexer, options)?;
 
         // Report timings
         if options.profile {
-            profiler.report("Interpreting and optimizing");
+            profiler.report("Interpreting");
         }
 
-        start
+        Ok(start)
     }
 
-    /// Interpret the given stream of bytes into a routine.
-    /// This routine may be simple, or it may be conditional with makes the routine
-    /// loopable.
+    /// Interpret the given program tokens into a routine containing the
+    /// whole program.
     ///
-    /// The byte stream should be given to `bytes`.
+    /// The lexer providing program tokens should be given to `lexer`.
     ///
-    /// If `cond` is `true`, this routine is loopable, `false` if it isn't.
-    fn interpret_routine(bytes: &mut Bytes, cond: bool) -> Op {
-        // Interpret the contained routine operations
-        let ops = Interpreter::interpret_vec(bytes);
-
-        // Optimize the routine
-        if let Some(opt) = optimize_routine(cond, &ops) {
-            return opt;
-        }
-
-        // Wrap the oprations in a routine as normal
-        Op::Routine(ops, cond)
-    }
-
-    /// Interpret the given stream of bytes into a vector of operations.
+    /// Nested loops are interpreted using an explicit stack of unfinished
+    /// routines instead of recursion, so the nesting depth of a program is
+    /// only limited by available memory.
     ///
-    /// This function returns the vector if the input stream is empty,
-    /// or if a loop-end operator has been reached.
-    ///
-    /// The byte stream should be given to `bytes`.
-    fn interpret_vec(bytes: &mut Bytes) -> Vec<Op> {
-        // Create an operations vector, and a workspace for the last operation
-        // being worked on
-        let mut ops = vec![];
-        let mut workspace = None;
+    /// An error is returned if a loop-end is found outside a loop, or if the
+    /// source ends inside a loop.
+    fn interpret_tokens(lexer: &mut dyn Lexer, options: &Options)
+        -> Result<Op, ParseError>
+    {
+        // The routine being worked on, and the stack of routines it is
+        // nested in
+        let mut routine = Routine::new(None, lexer.current());
+        let mut stack: Vec<Routine> = vec![];
 
-        // Interpret all bytes until we break
+        // Interpret all tokens until we break
         loop {
-            // Find the next byte to process, or break if the stream is emtpy
-            let byte = if let Some(byte) = bytes.next() {
-                byte
+            // Find the next token to process, or break if the source is emtpy
+            let (token, span) = if let Some(next) = lexer.next_token() {
+                next
+            } else if let Some(open) = routine.open {
+                return Err(ParseError::Unclosed(open.start, lexer.current()));
             } else {
                 break;
             };
 
-            // Process the byte
-            match byte {
+            // Process the token
+            let Routine { ref mut ops, ref mut workspace, .. } = routine;
+            match token {
                 // Seek up
-                b'>' => Interpreter::process_workspace_seek(
-                    &mut workspace,
-                    &mut ops,
+                Token::Right => Interpreter::process_workspace_seek(
+                    workspace,
+                    ops,
                     1,
+                    span,
                 ),
 
                 // Seek down
-                b'<' => Interpreter::process_workspace_seek(
-                    &mut workspace,
-                    &mut ops,
+                Token::Left => Interpreter::process_workspace_seek(
+                    workspace,
+                    ops,
                     -1,
+                    span,
                 ),
 
                 // Increase memory cell value
-                b'+' => Interpreter::process_workspace_inc(
-                    &mut workspace,
-                    &mut ops,
+                Token::Inc => Interpreter::process_workspace_inc(
+                    workspace,
+                    ops,
                     1,
+                    span,
                 ),
 
                 // Decrease memory cell value
-                b'-' => Interpreter::process_workspace_inc(
-                    &mut workspace,
-                    &mut ops,
+                Token::Dec => Interpreter::process_workspace_inc(
+                    workspace,
+                    ops,
                     -1,
+                    span,
                 ),
 
                 // Output the value of the current memory cell
-                b'.' => {
+                Token::Output => {
                     // Commit and add a new operator
-                    Interpreter::commit(&mut workspace, &mut ops, None);
-                    ops.push(Op::Output);
+                    Interpreter::commit(workspace, ops, None);
+                    ops.push(Op::Output(0, span));
                 },
 
                 // Read user input
-                b',' => {
+                Token::Input => {
                     // Commit and add a new operator
-                    Interpreter::commit(&mut workspace, &mut ops, None);
-                    ops.push(Op::Input);
+                    Interpreter::commit(workspace, ops, None);
+                    ops.push(Op::Input(0, span));
                 },
 
-                // Start a conditional loop
-                b'[' => {
-                    // Commit and add a new conditional routine
-                    Interpreter::commit(&mut workspace, &mut ops, None);
-                    ops.push(
-                        Interpreter::interpret_routine(bytes, true),
-                    );
+                // Dump the memory state if debugging, never fold across it
+                Token::Debug => if options.debug {
+                    Interpreter::commit(workspace, ops, None);
+                    ops.push(Op::Debug(span));
                 },
 
-                // End a conditional loop, finish this operation vector
-                b']' => break,
+                // Start a conditional loop, continue in a new routine
+                Token::Open => {
+                    Interpreter::commit(workspace, ops, None);
+                    stack.push(
+                        mem::replace(&mut routine, Routine::new(Some(span), span.start)),
+                    );
+                },
 
-                // Unrecognized operation, skip
-                _ => continue,
+                // End a conditional loop, add it to the outer routine
+                Token::Close => {
+                    let outer = stack.pop()
+                        .ok_or(ParseError::UnmatchedClose(span.start))?;
+                    let inner = mem::replace(&mut routine, outer)
+                        .finish(lexer.current());
+                    routine.ops.push(inner);
+                },
             }
         }
 
-        // Commit the last workspace operation
-        if let Some(op) = workspace {
-            ops.push(op);
-        }
-
-        ops
+        Ok(routine.finish(lexer.current()))
     }
 
     /// Commit the given workspace in the given.
@@ -174,23 +182,28 @@ impl Interpreter {
     /// for possible further optimizations in upcomming instructions.
     ///
     /// The `workspace` is committed to `ops`.
+    /// The `span` of the instruction is joined with the workspace span.
     fn process_workspace_seek(
         workspace: &mut Option<Op>,
         ops: &mut Vec<Op>,
         amount: isize,
+        span: Span,
     ) {
         // Determine whether to combine to an existing workspace,
         // or to commit and define a new operator workspace
         match *workspace {
             // Combine with the workspace operation
-            Some(Op::Seek(ref mut current)) => *current += amount,
+            Some(Op::Seek(ref mut current, ref mut current_span)) => {
+                *current += amount;
+                *current_span = current_span.join(span);
+            },
 
             // Commit the workspace, start working on a new seek operator
             _ => Interpreter::commit(
                 workspace,
                 ops,
                 Some(
-                    Op::Seek(amount),
+                    Op::Seek(amount, span),
                 ),
             ),
         }
@@ -209,25 +222,75 @@ impl Interpreter {
     /// for possible further optimizations in upcomming instructions.
     ///
     /// The `workspace` is committed to `ops`.
+    /// The `span` of the instruction is joined with the workspace span.
     fn process_workspace_inc(
         workspace: &mut Option<Op>,
         ops: &mut Vec<Op>,
         amount: isize,
+        span: Span,
     ) {
         // Determine whether to combine to an existing workspace,
         // or to commit and define a new operator workspace
         match *workspace {
             // Combine with the workspace operation
-            Some(Op::Inc(ref mut current)) => *current += amount,
+            Some(Op::Inc(ref mut current, 0, ref mut current_span)) => {
+                *current += amount;
+                *current_span = current_span.join(span);
+            },
 
             // Commit the workspace, start working on a new increment operator
             _ => Interpreter::commit(
                 workspace,
                 ops,
                 Some(
-                    Op::Inc(amount),
+                    Op::Inc(amount, 0, span),
                 ),
             ),
         }
     }
 }
+
+
+
+/// A routine that is being interpreted.
+struct Routine {
+    /// The operations interpreted so far.
+    ops: Vec<Op>,
+
+    /// A workspace for the last operation being worked on.
+    workspace: Option<Op>,
+
+    /// The span of the loop-start, if this routine is loopable.
+    open: Option<Span>,
+
+    /// The position this routine starts at.
+    start: Position,
+}
+
+impl Routine {
+    /// Start interpreting a new routine at the given `start` position.
+    ///
+    /// If `open` is the span of a loop-start, this routine is loopable.
+    /// If it is `None`, the routine isn't loopable.
+    fn new(open: Option<Span>, start: Position) -> Routine {
+        Routine {
+            ops: vec![],
+            workspace: None,
+            open,
+            start,
+        }
+    }
+
+    /// Finish interpreting this routine, ending at the given `end` position.
+    /// This commits the workspace.
+    fn finish(self, end: Position) -> Op {
+        // Commit the last workspace operation
+        let Routine { mut ops, workspace, open, start } = self;
+        if let Some(op) = workspace {
+            ops.push(op);
+        }
+
+        // Wrap the oprations in a routine as normal
+        Op::Routine(ops, open.is_some(), Span::new(start, end))
+    }
+}
@@ -0,0 +1,298 @@
+//! # Static program checks
+//! Lints are static checks on an interpreted, unoptimized program, that warn
+//! about suspicious or useless program logic.
+//!
+//! Each lint has a code and a name, that can be used to suppress it.
+//!
+//! # Lints
+//! - `W001` `unreachable-loop`: a loop at the start of the program, that never
+//!   runs as all memory is zero.
+//! - `W002` `dead-loop`: a loop directly following another loop, that never
+//!   runs as the previous loop leaves the current cell zero.
+//! - `W003` `cancelling-inc`: adjacent increments that sum to zero, such as
+//!   `+-`. Increments that only partly cancel out, such as `++-`, aren't
+//!   reported.
+//! - `W004` `cancelling-seek`: adjacent seeks that sum to zero, such as `<>`.
+//! - `W005` `infinite-loop`: a loop only changing the current cell by an even
+//!   amount, which never ends for some values. The zeroing optimization
+//!   replaces such loops, hiding the problem.
+//! - `W006` `pointer-underflow`: the memory pointer provably moves below the
+//!   first memory cell.
+
+
+
+use std::slice;
+
+use super::{Op, Span};
+
+
+
+/// A lint, identifying a static check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintCode {
+    /// A loop at program start that never runs.
+    UnreachableLoop,
+
+    /// A loop directly following another loop, that never runs.
+    DeadLoop,
+
+    /// Adjacent increments that sum to zero.
+    CancellingInc,
+
+    /// Adjacent seeks that sum to zero.
+    CancellingSeek,
+
+    /// A loop that never ends for some values.
+    InfiniteLoop,
+
+    /// The memory pointer moves below the first memory cell.
+    PointerUnderflow,
+}
+
+impl LintCode {
+    /// Get a list of all lints.
+    pub fn all() -> &'static [LintCode] {
+        &[
+            LintCode::UnreachableLoop,
+            LintCode::DeadLoop,
+            LintCode::CancellingInc,
+            LintCode::CancellingSeek,
+            LintCode::InfiniteLoop,
+            LintCode::PointerUnderflow,
+        ]
+    }
+
+    /// Get the code of this lint.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            LintCode::UnreachableLoop => "W001",
+            LintCode::DeadLoop => "W002",
+            LintCode::CancellingInc => "W003",
+            LintCode::CancellingSeek => "W004",
+            LintCode::InfiniteLoop => "W005",
+            LintCode::PointerUnderflow => "W006",
+        }
+    }
+
+    /// Get the name of this lint.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LintCode::UnreachableLoop => "unreachable-loop",
+            LintCode::DeadLoop => "dead-loop",
+            LintCode::CancellingInc => "cancelling-inc",
+            LintCode::CancellingSeek => "cancelling-seek",
+            LintCode::InfiniteLoop => "infinite-loop",
+            LintCode::PointerUnderflow => "pointer-underflow",
+        }
+    }
+
+    /// Find the lint with the given code or name.
+    pub fn find(code: &str) -> Option<LintCode> {
+        LintCode::all()
+            .iter()
+            .find(|lint| lint.code().eq_ignore_ascii_case(code) || lint.name() == code)
+            .cloned()
+    }
+}
+
+
+
+/// A lint warning, for a span of program source.
+pub struct Lint {
+    /// The lint that was triggered.
+    pub code: LintCode,
+
+    /// A message describing the problem.
+    pub message: String,
+
+    /// The span of program source the problem is in.
+    pub span: Span,
+}
+
+impl Lint {
+    /// Create a new lint warning.
+    fn new<S: Into<String>>(code: LintCode, message: S, span: Span) -> Lint {
+        Lint {
+            code,
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+
+
+/// Check the given interpreted program for problems.
+///
+/// The program should be interpreted without optimizations, as those hide
+/// many of the problems checked for.
+/// The found lints are returned in source order.
+pub fn lint(program: &Op) -> Vec<Lint> {
+    let mut lints = vec![];
+
+    // Get the top level operations
+    let top = match *program {
+        Op::Routine(ref ops, false, _) => ops.as_slice(),
+        _ => slice::from_ref(program),
+    };
+
+    // Check the program start, where memory is known
+    let start = lint_start(top, &mut lints);
+    lint_pointer(top, &mut lints);
+
+    // Check all operation sequences in the program
+    let mut stack = vec![(top, start)];
+    while let Some((ops, start)) = stack.pop() {
+        for (i, op) in ops.iter().enumerate() {
+            match *op {
+                Op::Inc(0, _, span) => lints.push(
+                    Lint::new(LintCode::CancellingInc, "increments cancel out", span),
+                ),
+
+                Op::Seek(0, span) => lints.push(
+                    Lint::new(LintCode::CancellingSeek, "seeks cancel out", span),
+                ),
+
+                Op::Routine(ref body, true, span) => {
+                    // Loops directly following a loop never run
+                    if i > start && leaves_zero(&ops[i - 1]) {
+                        lints.push(Lint::new(
+                            LintCode::DeadLoop,
+                            "loop never runs, the previous loop leaves the current cell zero",
+                            span,
+                        ));
+
+                    // Loops only adding an even amount may never end
+                    } else if let Some(amount) = inc_only(body) {
+                        if amount == 0 {
+                            lints.push(Lint::new(
+                                LintCode::InfiniteLoop,
+                                "loop never ends once entered, the current cell doesn't change",
+                                span,
+                            ));
+                        } else if amount % 2 == 0 {
+                            lints.push(Lint::new(
+                                LintCode::InfiniteLoop,
+                                format!("loop never ends for odd values, it changes the current cell by {}", amount),
+                                span,
+                            ));
+                        }
+                    }
+
+                    stack.push((body, 0));
+                },
+
+                _ => {},
+            }
+        }
+    }
+
+    lints.sort_by_key(|lint| lint.span.start.offset);
+    lints
+}
+
+/// Check for loops at the start of the program, that never run because all
+/// memory is zero.
+///
+/// The index of the first top level operation that may change memory is
+/// returned.
+fn lint_start(top: &[Op], lints: &mut Vec<Lint>) -> usize {
+    for (i, op) in top.iter().enumerate() {
+        match *op {
+            Op::Routine(_, true, span) => lints.push(Lint::new(
+                LintCode::UnreachableLoop,
+                "loop never runs, memory is zero at program start",
+                span,
+            )),
+            Op::Seek(..) | Op::Output(..) | Op::Debug(..) => {},
+            _ => return i,
+        }
+    }
+    top.len()
+}
+
+/// Check whether the memory pointer moves below the first memory cell.
+///
+/// The pointer position is only known in top level operations, until a loop
+/// is reached that doesn't return to the cell it started at.
+fn lint_pointer(top: &[Op], lints: &mut Vec<Lint>) {
+    let mut pointer = 0;
+    for op in top {
+        match *op {
+            Op::Seek(amount, span) => {
+                pointer += amount;
+                if pointer < 0 {
+                    lints.push(Lint::new(
+                        LintCode::PointerUnderflow,
+                        format!("memory pointer moves {} cells below the first cell", -pointer),
+                        span,
+                    ));
+                    return;
+                }
+            },
+            Op::Routine(ref body, _, _) if !balanced(body) => return,
+            _ => {},
+        }
+    }
+}
+
+/// Check whether the given operation leaves the current cell zero.
+///
+/// Only loops do so, as the program isn't optimized.
+fn leaves_zero(op: &Op) -> bool {
+    matches!(*op, Op::Routine(_, true, _))
+}
+
+/// If the given operations only increment the current cell, get the total
+/// amount it is incremented by.
+fn inc_only(ops: &[Op]) -> Option<isize> {
+    ops.iter()
+        .map(|op| match *op {
+            Op::Inc(amount, 0, _) => Some(amount),
+            _ => None,
+        })
+        .sum()
+}
+
+/// Check whether the given operations, and all nested routines, return the
+/// memory pointer to the cell they started at.
+fn balanced(ops: &[Op]) -> bool {
+    let mut stack = vec![ops];
+    while let Some(ops) = stack.pop() {
+        let mut offset = 0;
+        for op in ops {
+            match *op {
+                Op::Seek(amount, _) => offset += amount,
+                Op::Routine(ref body, _, _) => stack.push(body),
+                _ => {},
+            }
+        }
+        if offset != 0 {
+            return false;
+        }
+    }
+    true
+}
+
+
+
+/// Test whether each lint is found in a program that should trigger it.
+#[test]
+fn test_lint() {
+    use super::{Interpreter, Options};
+
+    let options = Options::default(true, false, false, false);
+    let codes = |prog: &str| -> Vec<&str> {
+        let program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+        lint(&program).iter().map(|lint| lint.code.code()).collect()
+    };
+
+    assert_eq!(codes("[comment.]>[-]+[->+<]"), vec!["W001", "W001"]);
+    assert_eq!(codes("+[->+<][comment]"), vec!["W002"]);
+    assert_eq!(codes("+.+-.><"), vec!["W003", "W004"]);
+    assert_eq!(codes("+.++-.>><"), Vec::<&str>::new());
+    assert_eq!(codes("+[--]+[]"), vec!["W005", "W005"]);
+    assert_eq!(codes("+>[-<+>]<<+"), vec!["W006"]);
+    assert_eq!(codes("+[>]<<+"), Vec::<&str>::new());
+    assert_eq!(LintCode::find("dead-loop"), Some(LintCode::DeadLoop));
+}
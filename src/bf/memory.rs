@@ -1,3 +1,7 @@
+use super::Affine;
+
+
+
 /// The size of the memory.
 const MEM_SIZE: usize = 30_000;
 
@@ -7,6 +11,7 @@ const MEM_SIZE: usize = 30_000;
 ///
 /// This struct defines the state of such a program,
 /// and provides helper functions to easily manage it.
+#[derive(Clone)]
 pub struct Memory {
     /// The memory data set
     data: [u8; MEM_SIZE],
@@ -48,41 +53,125 @@ impl Memory {
         }
     }
 
-    /// Increase the value of the current memory cell by the given relative
-    /// `amount`.
+    /// Get the index of the memory cell at the given `offset` relative to
+    /// the memory cell pointer.
     ///
-    /// The pointer won't underflow as specified by the brainfuck
-    /// specification. Instead the memory cell would be set to zero.
-    /// Overflowing is allowed.
-    pub fn inc(&mut self, amount: isize) {
-        self.data[self.pointer] = Memory::inc_virtual(
-            self.data[self.pointer],
-            amount,
-        );
+    /// The index won't underflow, like seeking the pointer.
+    fn cell(&self, offset: isize) -> usize {
+        Memory::seek_virtual(self.pointer, offset)
+    }
+
+    /// Increase the value of the memory cell at the given `offset` by the
+    /// given relative `amount`, wrapping around.
+    pub fn inc(&mut self, offset: isize, amount: isize) {
+        let cell = self.cell(offset);
+        self.data[cell] = Memory::inc_virtual(self.data[cell], amount);
     }
 
     /// Increate a virutal memory cell by the given relative `amount`.
     /// The new memory cell value is returned.
     ///
-    /// This method follows the brainfuck specification, not allowing the
-    /// index to underflow. If the number would underflow, zero is returned.
-    /// Overflowing is allowed.
+    /// Memory cells are 8-bit, and wrap around when overflowing or
+    /// underflowing.
     fn inc_virtual(value: u8, amount: isize) -> u8 {
-        if amount >= 0 {
-            value + (amount as u8)
-        } else {
-            value.saturating_sub(-amount as u8)
+        value.wrapping_add(amount as u8)
+    }
+
+    /// Seek the memory cell pointer in steps of the given `stride`, until a
+    /// cell with a zero value is found. The current cell is checked first.
+    ///
+    /// The pointer won't underflow, like seeking. Seeking down therefore
+    /// stops at the first cell if it is zero. If it isn't, the scan would
+    /// never end.
+    ///
+    /// `false` is returned without moving the pointer if no zero cell is
+    /// found, before the end of memory or when seeking down.
+    pub fn scan(&mut self, stride: isize) -> bool {
+        match self.find_zero(stride) {
+            Some(pointer) => {
+                self.pointer = pointer;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Find the cell a scan with the given `stride` would stop at.
+    /// See `scan` for details.
+    ///
+    /// `None` is returned if the scan wouldn't find a zero cell.
+    fn find_zero(&self, stride: isize) -> Option<usize> {
+        let step = stride.unsigned_abs();
+        match stride {
+            // Search the bytes directly when scanning cell by cell
+            1 => self.data[self.pointer..]
+                .iter()
+                .position(|&cell| cell == 0)
+                .map(|i| self.pointer + i),
+            -1 => self.data[..self.pointer + 1]
+                .iter()
+                .rposition(|&cell| cell == 0),
+
+            _ if stride > 0 => self.data[self.pointer..]
+                .iter()
+                .step_by(step)
+                .position(|&cell| cell == 0)
+                .map(|i| self.pointer + i * step),
+            _ => self.data[..self.pointer + 1]
+                .iter()
+                .rev()
+                .step_by(step)
+                .position(|&cell| cell == 0)
+                .map(|i| self.pointer - i * step)
+                .or(if self.data[0] == 0 { Some(0) } else { None }),
         }
     }
 
-    /// Read and return the value of the current memory cell.
-    pub fn read(&self) -> u8 {
-        self.data[self.pointer]
+    /// Check whether the memory cell at the given `offset` exists, and
+    /// doesn't lie past the end of memory.
+    pub fn contains(&self, offset: isize) -> bool {
+        self.cell(offset) < MEM_SIZE
+    }
+
+    /// Get the memory cells from the first cell up to the last non-zero
+    /// cell.
+    pub fn image(&self) -> &[u8] {
+        let len = self.data.iter()
+            .rposition(|&cell| cell != 0)
+            .map_or(0, |i| i + 1);
+        &self.data[..len]
+    }
+
+    /// Load the given memory image into the first memory cells, and set the
+    /// memory cell pointer to the given `pointer`.
+    pub fn load(&mut self, image: &[u8], pointer: usize) {
+        self.data[..image.len()].copy_from_slice(image);
+        self.pointer = pointer;
+    }
+
+    /// Get the index of the current memory cell pointer.
+    pub fn pointer(&self) -> usize {
+        self.pointer
     }
 
-    /// Write the given value to the current memory cell.
-    pub fn write(&mut self, value: u8) {
-        self.data[self.pointer] = value;
+    /// Get a window of memory cells around the current memory cell pointer,
+    /// spanning `radius` cells in both directions where available.
+    /// The index of the first cell is returned along with the cells.
+    pub fn window(&self, radius: usize) -> (usize, &[u8]) {
+        let start = self.pointer.saturating_sub(radius);
+        let end = (self.pointer + radius + 1).min(MEM_SIZE);
+        (start, &self.data[start..end])
+    }
+
+    /// Read and return the value of the memory cell at the given `offset`.
+    pub fn read(&self, offset: isize) -> u8 {
+        self.data[self.cell(offset)]
+    }
+
+    /// Write the given value to the memory cell at the given `offset`.
+    pub fn write(&mut self, offset: isize, value: u8) {
+        let cell = self.cell(offset);
+        self.data[cell] = value;
     }
 
     /// Check whether the current memory cell is zero.
@@ -90,18 +179,77 @@ impl Memory {
         self.data[self.pointer] == 0
     }
 
-    /// Set the current memory cell value to zero.
-    pub fn set_zero(&mut self) {
-        self.data[self.pointer] = 0;
+    /// Set the value of the memory cell at the given `offset` to zero.
+    pub fn set_zero(&mut self, offset: isize) {
+        let cell = self.cell(offset);
+        self.data[cell] = 0;
+    }
+
+    /// Set the values of `len` memory cells from the given `offset` to zero.
+    ///
+    /// Cells before the first cell are clamped to it, like seeking the
+    /// pointer.
+    pub fn zero_range(&mut self, offset: isize, len: usize) {
+        let start = self.cell(offset);
+        let end = self.cell(offset + len as isize - 1) + 1;
+        self.data[start..end].fill(0);
     }
 
-    /// Move the current cell value to the given relative targets,
-    /// zeroing the current cell.
+    /// Move the values of the memory cells from the `first` to the `last`
+    /// given offset by the given `distance`, adding them to the cells there
+    /// and zeroing the cells moved from.
     ///
-    /// The targets 
-    pub fn copy_zero(&mut self, targets: &Vec<(isize, f32)>) {
+    /// The cells are moved one at a time in the given order, so a cell may
+    /// only be moved to a cell of the range if that cell was moved before.
+    /// The range is then moved as a block, using slice operations.
+    /// Near the start of memory, where cells before the first cell are
+    /// clamped to it, the cells are moved one at a time instead.
+    pub fn move_range(&mut self, first: isize, last: isize, distance: isize) {
+        let low = first.min(last);
+        let len = (last - first).unsigned_abs() + 1;
+        if self.pointer as isize + low + distance.min(0) < 0 {
+            let targets = vec![(distance, 1)];
+            let step = if last >= first { 1 } else { -1 };
+            let mut at = first;
+            loop {
+                self.copy_zero(at, &targets);
+                if at == last {
+                    return;
+                }
+                at += step;
+            }
+        }
+
+        // Add the cells moved to outside the range, shift the cells moved
+        // to inside it, and zero the cells that are left
+        let start = self.cell(low);
+        let shift = distance.unsigned_abs();
+        let outside = shift.min(len);
+        if distance > 0 {
+            let (from, to) = self.data.split_at_mut(start + len);
+            for (target, &value) in to[shift - outside..shift].iter_mut().zip(&from[start + len - outside..]) {
+                *target = target.wrapping_add(value);
+            }
+            self.data.copy_within(start..start + len - outside, start + shift);
+            self.data[start..start + outside].fill(0);
+        } else {
+            let (to, from) = self.data.split_at_mut(start);
+            for (target, &value) in to[start - shift..start - shift + outside].iter_mut().zip(&from[..outside]) {
+                *target = target.wrapping_add(value);
+            }
+            self.data.copy_within(start + outside..start + len, start + outside - shift);
+            self.data[start + len - outside..start + len].fill(0);
+        }
+    }
+
+    /// Move the value of the cell at the given `offset` to the given targets
+    /// relative to it, zeroing the cell.
+    ///
+    /// The value is multiplied by the factor of each target, wrapping around.
+    pub fn copy_zero(&mut self, offset: isize, targets: &[(isize, u8)]) {
         // Read the cell value, return if it is zero
-        let value = self.data[self.pointer];
+        let cell = self.cell(offset);
+        let value = self.data[cell];
         if value == 0 {
             return;
         }
@@ -109,16 +257,97 @@ impl Memory {
         // Write the values
         for &(target, factor) in targets {
             // Determine the pointer position
-            let pointer = Memory::seek_virtual(self.pointer, target);
+            let pointer = Memory::seek_virtual(cell, target);
 
             // Increase the data in the cell
-            self.data[pointer] = Memory::inc_virtual(
-                self.data[pointer],
-                (value as f32 * factor) as isize,
-            );
+            self.data[pointer] = self.data[pointer]
+                .wrapping_add(value.wrapping_mul(factor));
+        }
+
+        // Zero the cell
+        self.data[cell] = 0;
+    }
+
+    /// Divide the current cell value at once, like the well known divmod
+    /// loop `[->-[>+>>]>[+[-<+>]>+>>]<<<<<]` on the cells from the given
+    /// `divisor` offset does. Cells between the current cell and the divisor
+    /// are incremented by the current cell value.
+    ///
+    /// The loop counts the divisor cell down to one, counting the remainder
+    /// cell after it up. Once it reaches one, it is reset to the sum of both,
+    /// which doesn't change, and the quotient cell after them is incremented.
+    /// The two cells after the quotient must be zero.
+    ///
+    /// `false` is returned if the cells can't be divided at once, leaving
+    /// memory untouched. The loop must be run instead.
+    pub fn div_mod(&mut self, divisor: isize) -> bool {
+        let value = self.data[self.pointer] as usize;
+        if value == 0 {
+            return true;
+        }
+
+        // The loop relies on two zero cells after the quotient
+        let a = Memory::seek_virtual(self.pointer, divisor);
+        if a + 4 >= MEM_SIZE || self.data[a + 3] != 0 || self.data[a + 4] != 0 {
+            return false;
+        }
+
+        // Count down the divisor, a zero cell counts down from 256
+        let count = if self.data[a] == 0 { 256 } else { self.data[a] as usize };
+        let sum = match self.data[a].wrapping_add(self.data[a + 1]) {
+            0 => 256,
+            sum => sum as usize,
+        };
+        if value < count {
+            self.data[a] = (count - value) as u8;
+            self.data[a + 1] = self.data[a + 1].wrapping_add(value as u8);
+        } else {
+            // The loop leaves the pointer if the remainder is reset from zero
+            if sum == 1 {
+                return false;
+            }
+            let rest = value - count;
+            self.data[a] = (sum - rest % sum) as u8;
+            self.data[a + 1] = (rest % sum) as u8;
+            self.data[a + 2] = self.data[a + 2].wrapping_add((1 + rest / sum) as u8);
+        }
+
+        // Copy to the cells in between, and zero the current cell
+        for cell in &mut self.data[self.pointer + 1..a] {
+            *cell = cell.wrapping_add(value as u8);
+        }
+        self.data[self.pointer] = 0;
+        true
+    }
+
+    /// Run a linear loop on the cell at the given `offset` at once, zeroing
+    /// the cell.
+    ///
+    /// The loop runs the cell value times `scale` times, wrapping around.
+    /// Each target relative to the cell is set to its base function plus the
+    /// number of runs times its step function, of the cell values before.
+    pub fn mul_add(&mut self, offset: isize, scale: u8, targets: &[(isize, Affine, Affine)]) {
+        // Read the cell value, return if it is zero
+        let cell = self.cell(offset);
+        let value = self.data[cell];
+        if value == 0 {
+            return;
+        }
+        let runs = value.wrapping_mul(scale);
+
+        // Evaluate all targets before writing any of them
+        let read = |at: isize| self.data[Memory::seek_virtual(cell, at)];
+        let values: Vec<(usize, u8)> = targets.iter()
+            .map(|&(target, ref base, ref step)| (
+                Memory::seek_virtual(cell, target),
+                base.eval(read).wrapping_add(runs.wrapping_mul(step.eval(read))),
+            ))
+            .collect();
+        for (pointer, value) in values {
+            self.data[pointer] = value;
         }
 
-        // Zero the current cell
-        self.set_zero();
+        // Zero the cell
+        self.data[cell] = 0;
     }
 }
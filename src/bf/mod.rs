@@ -1,16 +1,27 @@
 extern crate tty_read;
 
 mod bf;
+mod dialect;
+mod error;
+mod input;
 mod interpreter;
+mod lint;
 mod memory;
 mod op;
 mod optimize;
 mod options;
+mod source;
 
 // Reexport
 pub use self::bf::bf;
+pub use self::dialect::Dialect;
+pub use self::error::{Error, ParseError, RuntimeError};
+pub use self::input::Input;
 pub use self::interpreter::Interpreter;
+pub use self::lint::{lint, LintCode};
 pub use self::memory::Memory;
+pub use self::optimize::{Pipeline, ReportFormat};
 pub use self::options::Options;
+pub use self::source::{Position, Source, Span};
 
-use self::op::Op;
+use self::op::{Affine, Op};
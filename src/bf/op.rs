@@ -1,7 +1,17 @@
-use super::tty_read::TermReader;
+use std::fmt;
+use std::mem;
+use std::slice;
 
+use super::Input;
 use super::Memory;
 use super::Options;
+use super::RuntimeError;
+use super::Span;
+
+
+
+/// The number of memory cells around the pointer to dump when debugging.
+const DEBUG_RADIUS: usize = 8;
 
 
 
@@ -14,7 +24,14 @@ use super::Options;
 ///
 /// Brainfuck programs are translated into these operations,
 /// which will define the program structure in-memory for quick execution.
-#[derive(Debug)]
+///
+/// Operations accessing memory carry an offset relative to the memory
+/// pointer, so the pointer doesn't have to be moved for each access.
+/// The interpreter produces operations with a zero offset, see
+/// `optimize_offsets` for deferring pointer movement.
+///
+/// Each operation carries the span of program source it was translated from
+/// as last property.
 pub enum Op {
     /// A routine wrapping other operations.
     /// This routine may be simple, or it may be conditional with makes the
@@ -26,74 +43,298 @@ pub enum Op {
     /// conditional/loopable.
     /// - `true` if this routine is contitionally loopable.
     /// - `false` if it isn't.
-    Routine(Vec<Op>, bool),
+    Routine(Vec<Op>, bool, Span),
+
+    /// A routine that runs its operations once, if the current memory cell
+    /// isn't zero.
+    /// This is a loop of which the operations always leave the current cell
+    /// zero, so it never has to check whether to loop again.
+    If(Vec<Op>, Span),
 
     /// Seek the memory pointer for the relative amount.
-    Seek(isize),
+    Seek(isize, Span),
+
+    /// Increment the value in a memory cell with the relative amount.
+    /// The second property defines the offset of the cell.
+    Inc(isize, isize, Span),
+
+    /// Put a byte from user input into the memory cell at the given offset.
+    Input(isize, Span),
+
+    /// Output the value of the memory cell at the given offset.
+    Output(isize, Span),
+
+    /// Set the value of the memory cell at the given offset to zero.
+    Zero(isize, Span),
+
+    /// Set the values of a range of memory cells to zero.
+    /// The first property defines the offset of the first cell, the second
+    /// the number of cells.
+    ZeroRange(isize, usize, Span),
+
+    /// Set the value of a memory cell to the given value.
+    /// The second property defines the offset of the cell.
+    Set(u8, isize, Span),
 
-    /// Increment the value in the current memory cell with the relative
-    /// amount.
-    Inc(isize),
+    /// Add the cell value to the given targets relative to it,
+    /// multiplied by the given wrapping factors, zeroing the cell.
+    /// The second property defines the offset of the cell.
+    AddAndZero(Vec<(isize, u8)>, isize, Span),
 
-    /// Put a byte from user input into the current memory cell.
-    Input,
+    /// Move the values of a range of memory cells by the given distance,
+    /// adding them to the cells there and zeroing the range.
+    /// This copies the range if the cells moved to are zero.
+    /// The first two properties define the offsets of the cells at both ends
+    /// of the range, the cells are moved in that order. The third property
+    /// defines the distance.
+    MoveRange(isize, isize, isize, Span),
 
-    /// Output the value of the current memory cell.
-    Output,
+    /// Run a linear loop on the cell at the given offset at once, and zero
+    /// the cell.
+    /// The loop runs the cell value times the first property, wrapping
+    /// around. Each target relative to the cell is set to the first affine
+    /// function plus the number of runs times the second, both of the cell
+    /// values before the loop.
+    /// The third property defines the offset of the cell.
+    MulAdd(u8, Vec<(isize, Affine, Affine)>, isize, Span),
 
-    /// Set the value of the current memory cell to zero.
-    Zero,
+    /// Divide the current cell value by the divisor cell at once, like the
+    /// well known divmod loop on the cells from the divisor does.
+    /// The first property defines the offset of the divisor cell, the cells
+    /// in between are incremented by the current cell value.
+    /// The second property is the original loop, that is run instead if the
+    /// cells can't be divided at once.
+    DivMod(isize, Box<Op>, Span),
 
-    /// Add the current cell value to the given relative targets,
-    /// zeroing the current cell.
-    AddAndZero(Vec<(isize, f32)>),
+    /// Seek the memory pointer in steps of the given stride, until a cell
+    /// with a zero value is found.
+    Scan(isize, Span),
+
+    /// A loop that never ends once entered, as it doesn't change the current
+    /// cell.
+    /// Executing it reports the program hangs, unless the current cell is
+    /// zero.
+    Hang(Span),
+
+    /// Output the given literal bytes.
+    Print(Vec<u8>, Span),
+
+    /// Load the given memory image into the first memory cells, and set the
+    /// memory pointer to the given cell.
+    /// This restores the memory state of a program prefix that was evaluated
+    /// ahead of time.
+    Load(Vec<u8>, usize, Span),
+
+    /// Dump the memory pointer and the cells around it to stderr,
+    /// for debugging.
+    Debug(Span),
 }
 
 impl Op {
+    /// Get the span of program source this operation was translated from.
+    pub fn span(&self) -> Span {
+        match *self {
+            Op::Routine(_, _, span)
+                | Op::If(_, span)
+                | Op::Seek(_, span)
+                | Op::Inc(_, _, span)
+                | Op::Input(_, span)
+                | Op::Output(_, span)
+                | Op::Zero(_, span)
+                | Op::ZeroRange(_, _, span)
+                | Op::Set(_, _, span)
+                | Op::AddAndZero(_, _, span)
+                | Op::MoveRange(_, _, _, span)
+                | Op::MulAdd(_, _, _, span)
+                | Op::DivMod(_, _, span)
+                | Op::Scan(_, span)
+                | Op::Hang(span)
+                | Op::Print(_, span)
+                | Op::Load(_, _, span)
+                | Op::Debug(span) => span,
+        }
+    }
+
+    /// Check whether this operation only changes memory, and always ends
+    /// without any other effect.
+    pub fn changes_memory_only(&self) -> bool {
+        matches!(
+            *self,
+            Op::Seek(..) | Op::Inc(..) | Op::Zero(..) | Op::ZeroRange(..) | Op::Set(..) | Op::AddAndZero(..)
+                | Op::MoveRange(..) | Op::MulAdd(..) | Op::Load(..)
+        )
+    }
+
+    /// Check whether the memory cells this operation accesses directly all
+    /// lie before the end of the given `memory`.
+    /// Routines only access the current cell directly, to check it.
+    pub fn fits(&self, memory: &Memory) -> bool {
+        match *self {
+            Op::Inc(_, at, _) | Op::Input(at, _) | Op::Output(at, _) | Op::Zero(at, _) | Op::Set(_, at, _) =>
+                memory.contains(at),
+            Op::ZeroRange(at, len, _) => memory.contains(at + len as isize - 1),
+            Op::AddAndZero(ref targets, at, _) =>
+                memory.contains(at) && targets.iter().all(|&(target, _)| memory.contains(at + target)),
+            Op::MoveRange(first, last, distance, _) => {
+                let high = first.max(last);
+                memory.contains(high) && memory.contains(high + distance)
+            },
+            Op::MulAdd(_, ref targets, at, _) =>
+                memory.contains(at)
+                    && targets.iter().all(|&(target, ref base, ref step)| {
+                        memory.contains(at + target)
+                            && base.terms.iter().chain(&step.terms).all(|&(term, _)| memory.contains(at + term))
+                    }),
+            Op::Routine(_, true, _) | Op::If(..) | Op::DivMod(..) | Op::Scan(..) | Op::Hang(..) =>
+                memory.contains(0),
+            Op::Routine(_, false, _) | Op::Seek(..) | Op::Print(..) | Op::Load(..) | Op::Debug(..) => true,
+        }
+    }
+
     /// Execute the current operation.
     ///
     /// If this operation is a conditional routine, the condition is properly
     /// evaluated as expected.
     ///
-    /// The given `memory` and `output` objects are used to execute these
-    /// operations on, if relevant.
-    pub fn execute(&self, memory: &mut Memory, options: &Options, output: &mut Vec<u8>) {
+    /// Nested routines are executed using an explicit stack instead of
+    /// recursion, so the nesting depth is only limited by available memory.
+    ///
+    /// The given `memory`, `input` and `output` objects are used to execute
+    /// these operations on, if relevant.
+    ///
+    /// An error is returned if the program hangs in a loop that never ends,
+    /// or if it accesses memory past the end of it.
+    pub fn execute(
+        &self,
+        memory: &mut Memory,
+        options: &Options,
+        input: &mut Input,
+        output: &mut Vec<u8>,
+    ) -> Result<(), RuntimeError> {
+        // The routines being executed, with their condition and the index of
+        // the next operation to execute in them
+        let mut stack: Vec<(&[Op], bool, usize)> = vec![];
+        let mut op = self;
+
+        loop {
+            match *op {
+                // Report checking a cell past the end of memory
+                Op::Routine(_, true, span) | Op::If(_, span) | Op::DivMod(_, _, span) | Op::Hang(span)
+                    | Op::Scan(_, span) if !memory.contains(0) =>
+                    return Err(RuntimeError::OutOfMemory(span.start)),
+
+                // Enter a routine
+                Op::Routine(ref ops, cond, _) => {
+                    // If conditional, skip the routine if the current memory
+                    // cell value is zero
+                    if !cond || !memory.zero() {
+                        stack.push((ops, cond, 0));
+                    }
+                },
+
+                // Enter a routine once, if the current memory cell isn't zero
+                Op::If(ref ops, _) => if !memory.zero() {
+                    stack.push((ops, false, 0));
+                },
+
+                // Divide at once, or run the original loop
+                Op::DivMod(divisor, ref fallback, _) => if !memory.div_mod(divisor) {
+                    stack.push((slice::from_ref(fallback), false, 0));
+                },
+
+                // Report entering a loop that never ends
+                Op::Hang(span) => if !memory.zero() {
+                    return Err(RuntimeError::Hang(span.start));
+                },
+
+                // Seek to the next zero cell, report scanning down forever
+                // from a first cell that isn't zero, or scanning past the
+                // end of memory
+                Op::Scan(stride, span) => if !memory.scan(stride) {
+                    return Err(if stride > 0 {
+                        RuntimeError::OutOfMemory(span.start)
+                    } else {
+                        RuntimeError::Hang(span.start)
+                    });
+                },
+
+                // Execute other operations directly
+                _ => op.execute_single(memory, options, input, output)?,
+            }
+
+            // Find the next operation to execute, leave finished routines
+            op = loop {
+                match stack.last_mut() {
+                    Some(&mut (ops, cond, ref mut index)) => {
+                        if let Some(next) = ops.get(*index) {
+                            *index += 1;
+                            break next;
+                        }
+
+                        // Loop the routine again if it is conditional, and
+                        // the current memory cell value isn't zero
+                        if cond && !memory.contains(0) {
+                            return Err(RuntimeError::OutOfMemory(op.span().start));
+                        }
+                        if cond && !memory.zero() {
+                            *index = 0;
+                            continue;
+                        }
+                    },
+                    None => return Ok(()),
+                }
+                stack.pop();
+            };
+        }
+    }
+
+    /// Execute the current operation, which must not be a routine.
+    ///
+    /// The given `memory`, `input` and `output` objects are used to execute
+    /// these operations on, if relevant.
+    ///
+    /// An error is returned if the operation accesses memory past the end of
+    /// it.
+    fn execute_single(
+        &self,
+        memory: &mut Memory,
+        options: &Options,
+        input: &mut Input,
+        output: &mut Vec<u8>,
+    ) -> Result<(), RuntimeError> {
         // Invoke operation specific logic
         match *self {
+            // Report accessing a cell past the end of memory
+            Op::Inc(_, offset, span) | Op::Zero(offset, span) | Op::Set(_, offset, span) | Op::Output(offset, span)
+                | Op::Input(offset, span) if !memory.contains(offset) =>
+                return Err(RuntimeError::OutOfMemory(span.start)),
+            Op::ZeroRange(.., span) | Op::AddAndZero(.., span) | Op::MoveRange(.., span) | Op::MulAdd(.., span)
+                if !self.fits(memory) =>
+                return Err(RuntimeError::OutOfMemory(span.start)),
+
             // Seek the memory cell pointer
-            Op::Seek(amount) => memory.seek(amount),
+            Op::Seek(amount, _) => memory.seek(amount),
 
-            // Increase the value in the current memory cell
-            Op::Inc(amount) => memory.inc(amount),
+            // Increase the value in a memory cell
+            Op::Inc(amount, offset, _) => memory.inc(offset, amount),
 
-            // Invoke a routine
-            Op::Routine(ref ops, cond) => {
-                // If conditional, skip the routine if the current memory cell
-                // value is zero
-                if cond && memory.zero() {
-                    return;
-                }
+            // Routines and scans are executed by `execute`
+            Op::Routine(..) | Op::If(..) | Op::DivMod(..) | Op::Hang(..) | Op::Scan(..) =>
+                unreachable!("routines must be executed through execute"),
 
-                // Keep looping the routine until the end condition is reached
-                loop {
-                    // Execute all contained operations
-                    ops.iter().for_each(|op| op.execute(memory, options, output));
+            // Set the value of a memory cell to zero
+            Op::Zero(offset, _) => memory.set_zero(offset),
 
-                    // End if not conditional, or if the current memory cell
-                    // value is zero
-                    if !cond || memory.zero() {
-                        break;
-                    }
-                }
-            },
+            // Set the values of a range of memory cells to zero
+            Op::ZeroRange(offset, len, _) => memory.zero_range(offset, len),
 
-            // Set the value of the current memory cell to zero
-            Op::Zero => memory.set_zero(),
+            // Set the value of a memory cell
+            Op::Set(value, offset, _) => memory.write(offset, value),
 
-            // Output the value of the current memory cell
-            Op::Output => {
+            // Output the value of a memory cell
+            Op::Output(offset, _) => {
                 // Read the value, and push it to the output
-                let value = memory.read();
+                let value = memory.read(offset);
                 output.push(value);
 
                 // If not buffered, print the value immediately
@@ -102,16 +343,218 @@ impl Op {
                 }
             },
 
+            // Output literal bytes
+            Op::Print(ref bytes, _) => {
+                output.extend_from_slice(bytes);
+
+                // If not buffered, print the bytes immediately
+                if !options.buffer {
+                    print!("{}", bytes.iter().map(|&byte| byte as char).collect::<String>());
+                }
+            },
+
+            // Restore an evaluated memory state
+            Op::Load(ref image, pointer, _) => memory.load(image, pointer),
+
             // Handle user input
-            Op::Input => memory.write(
-                TermReader::open_stdin(&options.reader_options)
-                    .expect("failed to open user input reader")
-                    .read_byte()
-                    .expect("failed to read user input")
-            ),
-
-            // Add the current cell value to others, and zero
-            Op::AddAndZero(ref targets) => memory.copy_zero(targets),
+            Op::Input(offset, _) => memory.write(offset, input.read(options)),
+
+            // Add a cell value to others, and zero
+            Op::AddAndZero(ref targets, offset, _) => memory.copy_zero(offset, targets),
+
+            // Move a range of memory cells
+            Op::MoveRange(first, last, distance, _) => memory.move_range(first, last, distance),
+
+            // Run a linear loop at once
+            Op::MulAdd(scale, ref targets, offset, _) => memory.mul_add(offset, scale, targets),
+
+            // Dump the memory state, without touching program output
+            Op::Debug(span) => {
+                let (start, cells) = memory.window(DEBUG_RADIUS);
+                let cells: Vec<String> = cells.iter()
+                    .enumerate()
+                    .map(|(i, value)| if start + i == memory.pointer() {
+                        format!("[{}]", value)
+                    } else {
+                        value.to_string()
+                    })
+                    .collect();
+                eprintln!(
+                    "# debug at {:?}: pointer {}, cells {}..: {}",
+                    span.start,
+                    memory.pointer(),
+                    start,
+                    cells.join(" "),
+                );
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// An affine function of memory cell values, wrapping around.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Affine {
+    /// The constant value.
+    pub constant: u8,
+
+    /// The cells to add, relative to some cell, multiplied by the given
+    /// factors.
+    pub terms: Vec<(isize, u8)>,
+}
+
+impl Affine {
+    /// Evaluate this function, reading the cell at each term offset using
+    /// the given `read` function.
+    pub fn eval<F: Fn(isize) -> u8>(&self, read: F) -> u8 {
+        self.terms.iter().fold(self.constant, |sum, &(at, factor)| {
+            sum.wrapping_add(read(at).wrapping_mul(factor))
+        })
+    }
+}
+
+/// A field of an operation, when formatting it for debugging.
+enum Field<'a> {
+    /// A value, formatted as text.
+    Value(String),
+
+    /// A nested operation.
+    Op(&'a Op),
+
+    /// A list of nested operations.
+    Ops(&'a [Op]),
+}
+
+/// A step in formatting operations for debugging.
+enum Step<'a> {
+    /// Write the given text.
+    Text(String),
+
+    /// Format the given operation, at the given pretty printing depth.
+    Op(&'a Op, usize),
+}
+
+impl fmt::Debug for Op {
+    /// Format operations like a derived implementation would, also when
+    /// pretty printing.
+    ///
+    /// Nested routines are formatted using an explicit stack instead of
+    /// recursively, so formatting a deeply nested program doesn't overflow
+    /// the stack.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pretty = f.alternate();
+        let indent = |depth: usize| format!("\n{}", "    ".repeat(depth));
+        let field = |value: &dyn fmt::Debug, depth: usize| Field::Value(if pretty {
+            format!("{:#?}", value).replace('\n', &indent(depth + 1))
+        } else {
+            format!("{:?}", value)
+        });
+
+        let mut stack = vec![Step::Op(self, 0)];
+        while let Some(step) = stack.pop() {
+            let (op, depth) = match step {
+                Step::Text(text) => {
+                    f.write_str(&text)?;
+                    continue;
+                },
+                Step::Op(op, depth) => (op, depth),
+            };
+
+            let (name, fields) = match *op {
+                Op::Routine(ref ops, cond, span) =>
+                    ("Routine", vec![Field::Ops(ops), field(&cond, depth), field(&span, depth)]),
+                Op::If(ref ops, span) => ("If", vec![Field::Ops(ops), field(&span, depth)]),
+                Op::Seek(amount, span) => ("Seek", vec![field(&amount, depth), field(&span, depth)]),
+                Op::Inc(amount, at, span) =>
+                    ("Inc", vec![field(&amount, depth), field(&at, depth), field(&span, depth)]),
+                Op::Input(at, span) => ("Input", vec![field(&at, depth), field(&span, depth)]),
+                Op::Output(at, span) => ("Output", vec![field(&at, depth), field(&span, depth)]),
+                Op::Zero(at, span) => ("Zero", vec![field(&at, depth), field(&span, depth)]),
+                Op::ZeroRange(at, len, span) =>
+                    ("ZeroRange", vec![field(&at, depth), field(&len, depth), field(&span, depth)]),
+                Op::Set(value, at, span) =>
+                    ("Set", vec![field(&value, depth), field(&at, depth), field(&span, depth)]),
+                Op::AddAndZero(ref targets, at, span) =>
+                    ("AddAndZero", vec![field(targets, depth), field(&at, depth), field(&span, depth)]),
+                Op::MoveRange(first, last, distance, span) => ("MoveRange", vec![
+                    field(&first, depth),
+                    field(&last, depth),
+                    field(&distance, depth),
+                    field(&span, depth),
+                ]),
+                Op::MulAdd(scale, ref targets, at, span) => ("MulAdd", vec![
+                    field(&scale, depth),
+                    field(targets, depth),
+                    field(&at, depth),
+                    field(&span, depth),
+                ]),
+                Op::DivMod(divisor, ref fallback, span) =>
+                    ("DivMod", vec![field(&divisor, depth), Field::Op(fallback), field(&span, depth)]),
+                Op::Scan(stride, span) => ("Scan", vec![field(&stride, depth), field(&span, depth)]),
+                Op::Hang(span) => ("Hang", vec![field(&span, depth)]),
+                Op::Print(ref bytes, span) => ("Print", vec![field(bytes, depth), field(&span, depth)]),
+                Op::Load(ref image, pointer, span) =>
+                    ("Load", vec![field(image, depth), field(&pointer, depth), field(&span, depth)]),
+                Op::Debug(span) => ("Debug", vec![field(&span, depth)]),
+            };
+
+            // Collect the steps to format the fields, run them in order
+            let mut steps = vec![Step::Text(format!("{}(", name))];
+            for (i, field) in fields.into_iter().enumerate() {
+                if pretty {
+                    steps.push(Step::Text(indent(depth + 1)));
+                } else if i > 0 {
+                    steps.push(Step::Text(", ".into()));
+                }
+                match field {
+                    Field::Value(text) => steps.push(Step::Text(text)),
+                    Field::Op(op) => steps.push(Step::Op(op, depth + 1)),
+                    Field::Ops(ops) => {
+                        steps.push(Step::Text("[".into()));
+                        for (i, op) in ops.iter().enumerate() {
+                            if pretty {
+                                steps.push(Step::Text(indent(depth + 2)));
+                            } else if i > 0 {
+                                steps.push(Step::Text(", ".into()));
+                            }
+                            steps.push(Step::Op(op, depth + 2));
+                            if pretty {
+                                steps.push(Step::Text(",".into()));
+                            }
+                        }
+                        if pretty && !ops.is_empty() {
+                            steps.push(Step::Text(indent(depth + 1)));
+                        }
+                        steps.push(Step::Text("]".into()));
+                    },
+                }
+                if pretty {
+                    steps.push(Step::Text(",".into()));
+                }
+            }
+            if pretty {
+                steps.push(Step::Text(indent(depth)));
+            }
+            steps.push(Step::Text(")".into()));
+            stack.extend(steps.into_iter().rev());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Op {
+    /// Drop nested routines iteratively instead of recursively,
+    /// so dropping a deeply nested program doesn't overflow the stack.
+    fn drop(&mut self) {
+        if let Op::Routine(ref mut ops, ..) | Op::If(ref mut ops, _) = *self {
+            let mut stack = mem::take(ops);
+            while let Some(mut op) = stack.pop() {
+                if let Op::Routine(ref mut ops, ..) | Op::If(ref mut ops, _) = op {
+                    stack.append(ops);
+                }
+            }
         }
     }
 }
@@ -1,5 +1,9 @@
+mod pass;
+mod pipeline;
+mod program;
+mod report;
 mod routine;
-mod routine_optimizer;
 
 // Reexport
-pub use self::routine_optimizer::optimize_routine;
+pub use self::pipeline::Pipeline;
+pub use self::report::ReportFormat;
@@ -0,0 +1,139 @@
+use std::mem;
+
+use super::super::{Op, Span};
+
+
+
+/// An optimization pass, that transforms a program into an equivalent
+/// program that executes quicker.
+pub trait Pass {
+    /// Get the name of this pass, used to select it.
+    fn name(&self) -> &'static str;
+
+    /// Run this pass on the given whole `program`.
+    /// The number of changes made to the program is returned.
+    fn run(&self, program: &mut Op) -> usize;
+}
+
+
+
+/// A pass that optimizes each routine on its own.
+///
+/// Routines are optimized bottom-up, so nested routines are optimized
+/// before the routine containing them. Routines that run once are only
+/// searched for nested routines.
+pub struct RoutinePass {
+    /// The name of this pass.
+    name: &'static str,
+
+    /// The routine optimization.
+    /// See `optimize_zero` for the signature.
+    optimize: fn(bool, &[Op], Span) -> Option<Op>,
+}
+
+impl RoutinePass {
+    /// Create a new pass with the given `name`, running the given routine
+    /// optimization.
+    pub fn new(
+        name: &'static str,
+        optimize: fn(bool, &[Op], Span) -> Option<Op>,
+    ) -> RoutinePass {
+        RoutinePass {
+            name,
+            optimize,
+        }
+    }
+}
+
+impl Pass for RoutinePass {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Run the routine optimization on all routines in the program.
+    ///
+    /// Nested routines are taken apart and rebuilt using an explicit stack
+    /// instead of recursion, so the nesting depth is only limited by
+    /// available memory.
+    fn run(&self, program: &mut Op) -> usize {
+        let mut changes = 0;
+
+        // The routines being rebuilt, with the operations left to process,
+        // the processed operations, the condition and span
+        // The condition is `None` for routines that run once
+        let mut stack = match *program {
+            Op::Routine(ref mut ops, cond, span) =>
+                vec![(mem::take(ops).into_iter(), vec![], Some(cond), span)],
+            _ => return 0,
+        };
+
+        loop {
+            let next = stack.last_mut().unwrap().0.next();
+            match next {
+                // Take nested routines apart, keep other operations
+                Some(mut op) => match op {
+                    Op::Routine(ref mut ops, cond, span) =>
+                        stack.push((mem::take(ops).into_iter(), vec![], Some(cond), span)),
+                    Op::If(ref mut ops, span) =>
+                        stack.push((mem::take(ops).into_iter(), vec![], None, span)),
+                    _ => stack.last_mut().unwrap().1.push(op),
+                },
+
+                // Optimize finished routines, add them to the outer routine
+                None => {
+                    let (_, ops, cond, span) = stack.pop().unwrap();
+                    let op = match cond {
+                        Some(cond) => match (self.optimize)(cond, &ops, span) {
+                            Some(op) => {
+                                changes += 1;
+                                op
+                            },
+                            None => Op::Routine(ops, cond, span),
+                        },
+                        None => Op::If(ops, span),
+                    };
+                    match stack.last_mut() {
+                        Some(outer) => outer.1.push(op),
+                        None => {
+                            *program = op;
+                            return changes;
+                        },
+                    }
+                },
+            }
+        }
+    }
+}
+
+
+
+/// A pass that optimizes the whole program at once.
+pub struct ProgramPass {
+    /// The name of this pass.
+    name: &'static str,
+
+    /// The program optimization, returning the number of changes.
+    /// See `optimize_set` for the signature.
+    optimize: fn(&mut Op) -> usize,
+}
+
+impl ProgramPass {
+    /// Create a new pass with the given `name`, running the given program
+    /// optimization.
+    pub fn new(name: &'static str, optimize: fn(&mut Op) -> usize) -> ProgramPass {
+        ProgramPass {
+            name,
+            optimize,
+        }
+    }
+}
+
+impl Pass for ProgramPass {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self, program: &mut Op) -> usize {
+        (self.optimize)(program)
+    }
+}
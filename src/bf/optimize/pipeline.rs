@@ -0,0 +1,245 @@
+use super::super::Op;
+use super::pass::{Pass, ProgramPass, RoutinePass};
+use super::program::*;
+use super::report::Report;
+use super::routine::*;
+
+
+
+/// The maximum number of times passes are run when optimizing to a fixed
+/// point, in case passes keep changing the program.
+const MAX_ROUNDS: usize = 16;
+
+
+
+/// An optimization pipeline, running a list of passes in order.
+///
+/// The pipeline may run the passes repeatedly, until a fixed point is
+/// reached where none of the passes changes the program.
+///
+/// In strict mode, passes preserve the semantics of loops that never end.
+pub struct Pipeline {
+    /// The passes to run, in order.
+    passes: Vec<Box<dyn Pass>>,
+
+    /// Run the passes until the program doesn't change anymore.
+    fixed_point: bool,
+
+    /// Whether the passes are strict.
+    strict: bool,
+
+    /// The number of steps partial evaluation may evaluate.
+    budget: usize,
+}
+
+impl Pipeline {
+    /// Create all available passes, in their default order.
+    /// If `strict` is `true`, the strict variant of passes is used.
+    /// Partial evaluation evaluates at most `budget` steps.
+    ///
+    /// Routine passes run before the offset pass, as they match on seek
+    /// operations. They also match operations with offsets, as they run
+    /// after it again when optimizing to a fixed point. Divmod loops are
+    /// recognized once the loops in them are optimized. Loops are lowered
+    /// into routines running once before removing dead loops, which may
+    /// follow them. Ranges are combined from operations with deferred
+    /// offsets, after setting cells. Outputs of known cell values are printed
+    /// once cells are set. Partial evaluation runs late, so it evaluates
+    /// optimized operations. Cleanup runs last, removing what other passes
+    /// leave behind.
+    fn all(strict: bool, budget: usize) -> Vec<Box<dyn Pass>> {
+        let zero = if strict { optimize_zero_strict } else { optimize_zero };
+        vec![
+            Box::new(RoutinePass::new("zero", zero)),
+            Box::new(RoutinePass::new("scan", optimize_scan)),
+            Box::new(RoutinePass::new("add_and_zero", optimize_add_and_zero)),
+            Box::new(ProgramPass::new("divmod", optimize_div_mod)),
+            Box::new(ProgramPass::new("if", optimize_if)),
+            Box::new(ProgramPass::new("dead_loops", optimize_dead_loops)),
+            Box::new(ProgramPass::new("offsets", optimize_offsets)),
+            Box::new(ProgramPass::new("set", optimize_set)),
+            Box::new(ProgramPass::new("ranges", optimize_ranges)),
+            Box::new(ProgramPass::new("print", optimize_print)),
+            Box::new(PartialEval::new(budget)),
+            Box::new(ProgramPass::new("cleanup", optimize_cleanup)),
+        ]
+    }
+
+    /// Get the names of all available passes, in their default order.
+    pub fn names() -> Vec<&'static str> {
+        Pipeline::all(false, DEFAULT_BUDGET).iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Create a pipeline for the given optimization `level`.
+    ///
+    /// - `0`: no optimizations.
+    /// - `1`: routine optimizations.
+    /// - `2`: all optimizations, this is the default.
+    /// - `3`: all optimizations, run until a fixed point is reached.
+    pub fn level(level: u8) -> Pipeline {
+        let mut passes = Pipeline::all(false, DEFAULT_BUDGET);
+        match level {
+            0 => passes.clear(),
+            1 => {
+                passes = ["zero", "scan", "add_and_zero"].iter()
+                    .filter_map(|name| Pipeline::take(&mut passes, name))
+                    .collect();
+            },
+            _ => {},
+        }
+
+        Pipeline {
+            passes,
+            fixed_point: level >= 3,
+            strict: false,
+            budget: DEFAULT_BUDGET,
+        }
+    }
+
+    /// Create a pipeline running the passes with the given `names`, in the
+    /// given order.
+    /// If `fixed_point` is `true`, the passes are run until a fixed point is
+    /// reached.
+    ///
+    /// An error is returned if a pass name is unknown.
+    pub fn with_passes(names: &[&str], fixed_point: bool) -> Result<Pipeline, String> {
+        let mut all = Pipeline::all(false, DEFAULT_BUDGET);
+        let passes = names.iter()
+            .map(|name| Pipeline::take(&mut all, name).ok_or_else(|| Pipeline::unknown(name)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Pipeline {
+            passes,
+            fixed_point,
+            strict: false,
+            budget: DEFAULT_BUDGET,
+        })
+    }
+
+    /// Make the passes in this pipeline strict, preserving the semantics of
+    /// loops that never end.
+    pub fn strict(&mut self) {
+        self.strict = true;
+        self.rebuild();
+    }
+
+    /// Set the maximum number of steps partial evaluation may evaluate.
+    pub fn budget(&mut self, steps: usize) {
+        self.budget = steps;
+        self.rebuild();
+    }
+
+    /// Recreate the passes in this pipeline, to apply changed settings.
+    fn rebuild(&mut self) {
+        let mut all = Pipeline::all(self.strict, self.budget);
+        self.passes = self.passes.iter()
+            .filter_map(|pass| Pipeline::take(&mut all, pass.name()))
+            .collect();
+    }
+
+    /// Take the pass with the given `name` out of the list of `passes`.
+    fn take(passes: &mut Vec<Box<dyn Pass>>, name: &str) -> Option<Box<dyn Pass>> {
+        passes.iter()
+            .position(|pass| pass.name() == name)
+            .map(|i| passes.remove(i))
+    }
+
+    /// Disable the pass with the given `name`.
+    ///
+    /// An error is returned if the pass name is unknown. Disabling a pass
+    /// that isn't in this pipeline is allowed.
+    pub fn disable(&mut self, name: &str) -> Result<(), String> {
+        if !Pipeline::names().contains(&name) {
+            return Err(Pipeline::unknown(name));
+        }
+        self.passes.retain(|pass| pass.name() != name);
+        Ok(())
+    }
+
+    /// Create an error message for the unknown or repeated pass with the
+    /// given `name`, listing the available passes.
+    fn unknown(name: &str) -> String {
+        format!(
+            "unknown or repeated optimization pass '{}', available passes: {}",
+            name,
+            Pipeline::names().join(", "),
+        )
+    }
+
+    /// Run the passes of this pipeline on the given whole `program`.
+    ///
+    /// The name of each pass is returned, along with the total number of
+    /// changes it made.
+    pub fn run(&self, program: &mut Op) -> Vec<(&'static str, usize)> {
+        self.run_with(program, |_, pass, program| pass.run(program))
+    }
+
+    /// Run the passes of this pipeline on the given whole `program`, and
+    /// report what each pass did.
+    ///
+    /// This is slower than `run`, as the program is compared before and
+    /// after each pass.
+    pub fn report(&self, program: &mut Op) -> Report {
+        let mut report = Report::new(self.passes.iter().map(|pass| pass.name()), program);
+        self.run_with(program, |index, pass, program| report.record(index, pass, program));
+        report
+    }
+
+    /// Run the passes of this pipeline on the given whole `program`, using
+    /// `run` to run the pass at some index on it.
+    ///
+    /// The name of each pass is returned, along with the total number of
+    /// changes it made.
+    fn run_with<F>(&self, program: &mut Op, mut run: F) -> Vec<(&'static str, usize)>
+        where F: FnMut(usize, &dyn Pass, &mut Op) -> usize
+    {
+        let mut changes: Vec<_> = self.passes.iter()
+            .map(|pass| (pass.name(), 0))
+            .collect();
+
+        for _ in 0..MAX_ROUNDS {
+            // Run all passes, stop if nothing changed
+            let mut changed = false;
+            for (index, (pass, &mut (_, ref mut total))) in self.passes.iter().zip(changes.iter_mut()).enumerate() {
+                let count = run(index, &**pass, program);
+                *total += count;
+                changed |= count > 0;
+            }
+
+            if !self.fixed_point || !changed {
+                break;
+            }
+        }
+
+        changes
+    }
+}
+
+
+/// Test whether pipelines run the selected passes, in order.
+#[test]
+fn test_pipeline() {
+    use super::super::{Interpreter, Options};
+
+    let options = Options::default(true, false, false, false);
+    let run = |pipeline: &Pipeline| {
+        let mut program = Interpreter::interpret("+[-]>[>]<[->+<]".bytes(), &options).unwrap();
+        pipeline.run(&mut program)
+    };
+
+    assert!(run(&Pipeline::level(0)).is_empty());
+    assert_eq!(
+        run(&Pipeline::level(1)),
+        vec![("zero", 1), ("scan", 1), ("add_and_zero", 1)],
+    );
+    assert_eq!(
+        run(&Pipeline::with_passes(&["add_and_zero", "zero"], true).unwrap()),
+        vec![("add_and_zero", 1), ("zero", 1)],
+    );
+
+    let mut pipeline = Pipeline::level(3);
+    pipeline.disable("scan").unwrap();
+    assert!(run(&pipeline).iter().all(|&(name, _)| name != "scan"));
+    assert!(pipeline.disable("unknown").is_err());
+    assert!(Pipeline::with_passes(&["zero", "zero"], false).is_err());
+}
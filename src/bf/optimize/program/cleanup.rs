@@ -0,0 +1,169 @@
+//! # Cleanup
+//! This removes operations that have no effect, which are left behind by
+//! cancelling program source or by other passes.
+//!
+//! Operations are only merged while interpreting, so seeks and increments
+//! that end up next to each other after optimizing routines are merged here.
+//! Writes overwritten by a following zero or set operation are removed as
+//! well. Memory isn't observable after the program ends, so operations at the
+//! end of the program that only change memory are removed.
+//!
+//! # Optimization requirements
+//! - Seeks or increments by zero.
+//! - Adjacent seeks, or adjacent increments of the same cell.
+//! - Writes to a cell that is overwritten before anything reads it.
+//! - Operations at the end of the program, that only change memory.
+//!
+//! # Example routines
+//! - `+-<>`
+//!     - Remove everything
+//! - `.>[-]<.`
+//!     - Remove the seeks around the dead loop, once it is removed
+//! - `.+++[-]+`
+//!     - Remove the last increment, as memory isn't observable after it
+//!     - Remove everything after the output, once the zero optimization
+//!       replaced the loop
+
+
+
+use std::mem;
+
+use super::super::super::Op;
+
+
+
+/// Remove operations without effect from the given program.
+///
+/// The whole interpreted program should be given to `program`.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of removed operations is returned.
+pub fn optimize_cleanup(program: &mut Op) -> usize {
+    let mut removed = 0;
+    let mut stack: Vec<&mut Vec<Op>> = match *program {
+        Op::Routine(ref mut ops, false, _) => {
+            // Memory isn't observable after the end of the program
+            while ops.last().is_some_and(Op::changes_memory_only) {
+                ops.pop();
+                removed += 1;
+            }
+            vec![ops]
+        },
+        Op::Routine(ref mut ops, true, _) => vec![ops],
+        _ => return 0,
+    };
+
+    while let Some(ops) = stack.pop() {
+        let before = ops.len();
+
+        for op in mem::take(ops) {
+            // Merge with the previous operation, drop it if it cancels out
+            let merged = ops.last_mut().is_some_and(|prev| merge(prev, &op));
+            if merged {
+                if ops.last().is_some_and(no_op) {
+                    ops.pop();
+                }
+                continue;
+            }
+            if no_op(&op) {
+                continue;
+            }
+
+            // Drop earlier writes overwritten by this one
+            if let Op::Zero(at, _) | Op::Set(_, at, _) = op {
+                let start = ops.iter()
+                    .rposition(|op| write_offset(op).is_none())
+                    .map_or(0, |i| i + 1);
+                let kept: Vec<Op> = ops.drain(start..)
+                    .filter(|op| write_offset(op) != Some(at))
+                    .collect();
+                ops.extend(kept);
+            }
+
+            ops.push(op);
+        }
+        removed += before - ops.len();
+
+        // Process the routines
+        for op in ops {
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push(body);
+            }
+        }
+    }
+
+    removed
+}
+
+/// Merge the `next` seek or increment into the `prev` one.
+/// `true` is returned if the operations could be merged.
+fn merge(prev: &mut Op, next: &Op) -> bool {
+    let span = prev.span().join(next.span());
+    let merged = match (&*prev, next) {
+        (&Op::Seek(a, _), &Op::Seek(b, _)) => Op::Seek(a + b, span),
+        (&Op::Inc(a, at, _), &Op::Inc(b, offset, _)) if at == offset => Op::Inc(a + b, at, span),
+        _ => return false,
+    };
+    *prev = merged;
+    true
+}
+
+/// Check whether the given operation has no effect at all.
+fn no_op(op: &Op) -> bool {
+    match *op {
+        Op::Seek(0, _) => true,
+        Op::Inc(amount, _, _) => amount % 256 == 0,
+        Op::Print(ref bytes, _) => bytes.is_empty(),
+        _ => false,
+    }
+}
+
+/// Get the offset of the cell the given operation only writes to, without
+/// reading it or moving the memory pointer.
+fn write_offset(op: &Op) -> Option<isize> {
+    match *op {
+        Op::Inc(_, at, _) | Op::Zero(at, _) | Op::Set(_, at, _) => Some(at),
+        _ => None,
+    }
+}
+
+
+
+/// Test whether operations without effect are removed, and whether programs
+/// still produce the same output.
+#[test]
+fn test_cleanup() {
+    use super::super::super::{bf, Interpreter, Options, Pipeline};
+
+    let options = Options::default(true, false, false, false);
+    let cleanup = |prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+        let removed = optimize_cleanup(&mut program);
+        match program {
+            Op::Routine(ref ops, false, _) => (removed, ops.len()),
+            _ => panic!("expected a top level routine"),
+        }
+    };
+
+    assert_eq!(cleanup("+-<>."), (2, 1));
+    assert_eq!(cleanup(".+++[-]+"), (1, 3));
+    assert_eq!(cleanup("+[>+<-]>[-]<"), (1, 4));
+
+    // Increments overwritten by optimized loops are removed
+    let mut program = Interpreter::interpret(",+++[-].".bytes(), &options).unwrap();
+    Pipeline::with_passes(&["zero"], false).unwrap().run(&mut program);
+    assert_eq!(optimize_cleanup(&mut program), 1);
+    let mut program = Interpreter::interpret(".+++[-]+".bytes(), &options).unwrap();
+    Pipeline::with_passes(&["zero"], false).unwrap().run(&mut program);
+    assert_eq!(optimize_cleanup(&mut program), 3);
+
+    // Seeks around removed loops are merged
+    let mut program = Interpreter::interpret(".>[-]<.".bytes(), &options).unwrap();
+    Pipeline::with_passes(&["dead_loops"], false).unwrap().run(&mut program);
+    assert_eq!(optimize_cleanup(&mut program), 2);
+
+    assert_eq!(
+        bf(&mut "++>+++[<+>-]++[-]<.>>+".as_bytes(), &options).unwrap(),
+        "\u{5}",
+    );
+}
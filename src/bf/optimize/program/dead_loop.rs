@@ -0,0 +1,130 @@
+//! # Dead loop elimination
+//! This removes loops that can never run, because the current memory cell is
+//! known to be zero when the loop is reached.
+//!
+//! All memory is zero at program start, so loops before the first operation
+//! that changes memory never run. Such loops are often used as comment block
+//! containing prose. A loop only exits once the current cell is zero, so a
+//! loop directly following another loop never runs either.
+//!
+//! # Optimization requirements
+//! - A loop at program start, only preceded by seek, output and other dead
+//!   loops.
+//! - A loop directly following another loop.
+//!
+//! # Example routines
+//! - `[comment, with - characters.]+++.`
+//!     - Remove the comment loop
+//! - `+[->+<][-]`
+//!     - Remove the second loop
+
+
+
+use super::super::super::Op;
+
+
+
+/// What is known about memory, at some point in a sequence of operations.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Known {
+    /// All memory cells are zero.
+    AllZero,
+
+    /// The current memory cell is zero.
+    CurrentZero,
+
+    /// Nothing is known.
+    Unknown,
+}
+
+
+
+/// Remove loops that never run from the given program.
+///
+/// The whole interpreted program should be given to `program`.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of removed loops is returned.
+pub fn optimize_dead_loops(program: &mut Op) -> usize {
+    let mut removed = 0;
+
+    // All memory is zero at the start of the program
+    let mut stack: Vec<(&mut Vec<Op>, Known)> = match *program {
+        Op::Routine(ref mut ops, false, _) => vec![(ops, Known::AllZero)],
+        Op::Routine(ref mut ops, true, _) => vec![(ops, Known::Unknown)],
+        _ => return 0,
+    };
+
+    while let Some((ops, mut known)) = stack.pop() {
+        // Remove dead loops, keep track of what is known about memory
+        let before = ops.len();
+        ops.retain(|op| {
+            if known != Known::Unknown && loops(op) {
+                return false;
+            }
+            known = next_known(known, op);
+            true
+        });
+        removed += before - ops.len();
+
+        // Process the remaining loops, of which the current cell isn't zero
+        for op in ops {
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push((body, Known::Unknown));
+            }
+        }
+    }
+
+    removed
+}
+
+/// Check whether the given operation is a loop, or was optimized from one.
+fn loops(op: &Op) -> bool {
+    matches!(
+        *op,
+        Op::Routine(_, true, _) | Op::If(..) | Op::Zero(0, _) | Op::AddAndZero(_, 0, _)
+            | Op::MulAdd(_, _, 0, _) | Op::DivMod(..) | Op::Scan(..) | Op::Hang(..)
+    )
+}
+
+/// Get what is known about memory after running the given operation, if the
+/// given `known` is known before it.
+fn next_known(known: Known, op: &Op) -> Known {
+    match *op {
+        Op::Output(..) | Op::Print(..) | Op::Debug(..) => known,
+        Op::Seek(0, _) => known,
+        Op::Seek(..) if known == Known::AllZero => Known::AllZero,
+        _ if loops(op) => Known::CurrentZero,
+
+        // Ranges zeroing the current cell aren't loops, but leave it zero
+        Op::ZeroRange(at, len, _) if at <= 0 && 0 < at + len as isize => Known::CurrentZero,
+        Op::MoveRange(first, last, distance, _) => {
+            let within = |at: isize| first.min(last) <= at && at <= first.max(last);
+            if within(0) && !within(-distance) {
+                Known::CurrentZero
+            } else {
+                Known::Unknown
+            }
+        },
+        _ => Known::Unknown,
+    }
+}
+
+
+
+/// Test whether dead loops are removed, and other loops are kept.
+#[test]
+fn test_dead_loops() {
+    use super::super::super::{Interpreter, Options};
+
+    let options = Options::default(true, false, false, false);
+    let removed = |prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+        optimize_dead_loops(&mut program)
+    };
+
+    assert_eq!(removed("[comment, with - characters.]>.[more]+[-]"), 2);
+    assert_eq!(removed("+[->+<][-][+]>[-]"), 2);
+    assert_eq!(removed("+[>[-][comment]<-]"), 1);
+    assert_eq!(removed(",[.,]"), 0);
+}
@@ -0,0 +1,184 @@
+//! # Divmod optimization
+//! This recognizes the well known divmod loop, used by most programs that
+//! print numbers, and replaces it with a single division.
+//!
+//! The loop divides the current cell value by the divisor cell, one step
+//! for each unit of the value. Dividing large values takes many steps, and
+//! the loop moves the pointer back and forth, so other optimizations don't
+//! apply. The division is done at once instead.
+//!
+//! The loop only works if the cells it uses are laid out as expected. If
+//! they aren't, the original loop is run instead, so the program behaves the
+//! same for all cell values.
+//!
+//! This should run after the add and zero optimization, and before the
+//! offset optimization.
+//!
+//! # Optimization requirements
+//! - The divmod loop `[->-[>+>>]>[+[-<+>]>+>>]<<<<<]`.
+//! - Increments of cells between the current cell and the divisor are
+//!   allowed, copying the current cell value.
+//!
+//! # Example routines
+//! - `[->-[>+>>]>[+[-<+>]>+>>]<<<<<]`
+//!     - With `n d 0 0 0 0`, set the cells to `0 d-n%d n%d n/d 0 0`
+//! - `[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]`
+//!     - With `n 0 d 0 0 0 0`, set the cells to `0 n d-n%d n%d n/d 0 0`
+
+
+
+use std::mem;
+
+use super::super::super::Op;
+
+
+
+/// Replace divmod loops in the given program with a single division.
+///
+/// The whole program should be given to `program`.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of replaced loops is returned.
+pub fn optimize_div_mod(program: &mut Op) -> usize {
+    let mut changes = 0;
+    let mut stack: Vec<&mut Vec<Op>> = match *program {
+        Op::Routine(ref mut ops, _, _) => vec![ops],
+        _ => return 0,
+    };
+
+    while let Some(ops) = stack.pop() {
+        for op in ops {
+            let divisor = match *op {
+                Op::Routine(ref body, true, _) => divisor(body),
+                _ => None,
+            };
+            if let Some(divisor) = divisor {
+                let span = op.span();
+                let fallback = mem::replace(op, Op::Seek(0, span));
+                *op = Op::DivMod(divisor, Box::new(fallback), span);
+                changes += 1;
+                continue;
+            }
+
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push(body);
+            }
+        }
+    }
+
+    changes
+}
+
+/// Get the offset of the divisor cell, if the given loop body is a divmod
+/// loop.
+fn divisor(body: &[Op]) -> Option<isize> {
+    // Decrement the value, copy it to the cells before the divisor
+    let mut iter = body.iter();
+    match iter.next() {
+        Some(&Op::Inc(-1, 0, _)) => {},
+        _ => return None,
+    }
+    let mut divisor = 1;
+    let rest = loop {
+        match *iter.as_slice() {
+            [Op::Seek(1, _), Op::Inc(1, 0, _), ..] => {
+                divisor += 1;
+                iter.nth(1);
+            },
+            [Op::Seek(1, _), ref rest @ ..] => break rest,
+            _ => return None,
+        }
+    };
+
+    // Count down the divisor, and the remainder up
+    // Reset the divisor once it reaches zero, and increment the quotient
+    match *rest {
+        [
+            Op::Inc(-1, 0, _),
+            Op::Routine(ref count, true, _),
+            Op::Seek(1, _),
+            Op::Routine(ref reset, true, _),
+            Op::Seek(back, _),
+        ] if back == -divisor - 4 => match (&count[..], &reset[..]) {
+            (
+                [Op::Seek(1, _), Op::Inc(1, 0, _), Op::Seek(2, _)],
+                [Op::Inc(1, 0, _), ref restore, Op::Seek(1, _), Op::Inc(1, 0, _), Op::Seek(2, _)],
+            ) if moves_back(restore) => Some(divisor),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check whether the given operation moves the current cell value to the
+/// previous cell, either as `[-<+>]` loop or optimized.
+fn moves_back(op: &Op) -> bool {
+    match *op {
+        Op::AddAndZero(ref targets, 0, _) => targets[..] == [(-1, 1)],
+        Op::Routine(ref body, true, _) => matches!(
+            body[..],
+            [Op::Inc(-1, 0, _), Op::Seek(-1, _), Op::Inc(1, 0, _), Op::Seek(1, _)]
+        ),
+        _ => false,
+    }
+}
+
+
+
+/// Test whether divmod loops are replaced, and divide the same as the
+/// original loops for all 8-bit values.
+#[test]
+fn test_div_mod() {
+    use super::super::super::{Input, Interpreter, Memory, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    let interpret = |options: &Options, prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), options).unwrap();
+        options.pipeline.run(&mut program);
+        program
+    };
+    let run = |options: &Options, program: &Op, cells: &[u8]| {
+        let mut memory = Memory::new();
+        memory.seek(8);
+        for (i, &cell) in cells.iter().enumerate() {
+            memory.write(i as isize, cell);
+        }
+        program.execute(&mut memory, options, &mut Input::new(vec![], false), &mut vec![]).unwrap();
+        (memory.pointer(), memory.window(8).1.to_vec())
+    };
+
+    for prog in &["[->-[>+>>]>[+[-<+>]>+>>]<<<<<]", "[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]"] {
+        options.pipeline = Pipeline::with_passes(&["zero", "add_and_zero", "divmod"], false).unwrap();
+        let optimized = interpret(&options, prog);
+        match optimized {
+            Op::Routine(ref ops, false, _) => match ops[..] {
+                [Op::DivMod(..)] => {},
+                _ => panic!("expected a divmod operation: {:?}", ops),
+            },
+            _ => panic!("expected a top level routine"),
+        }
+        options.pipeline = Pipeline::level(0);
+        let original = interpret(&options, prog);
+
+        let divisor = if prog.starts_with("[->+") { 2 } else { 1 };
+        for n in 0..256 {
+            for d in 0..256 {
+                let mut cells = vec![0; 8];
+                cells[0] = n as u8;
+                cells[divisor] = d as u8;
+                cells[divisor + 1] = (n * d % 7) as u8;
+                cells[divisor + 2] = d as u8 / 3;
+                if (n + d) % 5 == 0 {
+                    cells[divisor + 1] = 0;
+                }
+                assert_eq!(
+                    run(&options, &optimized, &cells),
+                    run(&options, &original, &cells),
+                    "{} with {:?}",
+                    prog,
+                    cells,
+                );
+            }
+        }
+    }
+}
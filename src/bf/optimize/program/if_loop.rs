@@ -0,0 +1,221 @@
+//! # If optimization
+//! This lowers loops that run at most once into routines that don't check
+//! whether to loop again.
+//!
+//! A loop only runs again if the current cell isn't zero at the end of its
+//! body. If the body always leaves the cell it started at zero, such as a
+//! body ending with `[-]`, the loop runs once if the cell isn't zero, and
+//! never again.
+//!
+//! The body must return to the cell it started at, so the pointer offset
+//! must be known throughout it. Nested loops must return to the cell they
+//! started at as well.
+//! As any loop, the lowered routine leaves the current cell zero, so dead
+//! loops directly following it are removed by the dead loop optimization.
+//!
+//! # Optimization requirements
+//! - A loop that returns to the cell it started at.
+//! - The last operation writing the cell the loop started at must zero it.
+//!
+//! # Example routines
+//! - `[>+<[-]]`
+//!     - Increment the next cell once, if the current cell isn't zero
+//!     - Zero the current cell
+//! - `[.[->+<]]`
+//!     - Output the current cell, and move it to the next cell, if it isn't
+//!       zero
+//! - `[>[-]<[-]>>+<<]`
+//!     - Zero the next two cells, and increment the cell after, if the
+//!       current cell isn't zero
+
+
+
+use std::mem;
+
+use super::super::super::Op;
+
+
+
+/// What is known about a routine body, at some point in it.
+#[derive(Clone, Copy)]
+struct Known {
+    /// The offset of the memory pointer relative to the cell the body
+    /// started at, if known.
+    offset: Option<isize>,
+
+    /// Whether the cell the body started at is known to be zero.
+    zero: bool,
+}
+
+impl Known {
+    /// What is known at the start of a body.
+    fn start() -> Known {
+        Known {
+            offset: Some(0),
+            zero: false,
+        }
+    }
+
+    /// Get what is known after running the given operation.
+    ///
+    /// For routines, `balanced` should define whether their body returns to
+    /// the cell it started at.
+    fn after(self, op: &Op, balanced: bool) -> Known {
+        // Once the pointer offset is lost, nothing is known
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => return self,
+        };
+        let base = |at: isize| offset + at == 0;
+        let zero = |zero: bool| Known {
+            offset: Some(offset),
+            zero,
+        };
+        let lost = Known {
+            offset: None,
+            zero: false,
+        };
+
+        match *op {
+            Op::Seek(amount, _) => Known {
+                offset: Some(offset + amount),
+                zero: self.zero,
+            },
+            Op::Zero(at, _) if base(at) => zero(true),
+            Op::Set(value, at, _) if base(at) => zero(value == 0),
+            Op::Inc(_, at, _) | Op::Input(at, _) if base(at) => zero(false),
+            Op::AddAndZero(ref targets, at, _) => if base(at) {
+                zero(true)
+            } else if targets.iter().any(|&(target, _)| base(at + target)) {
+                zero(false)
+            } else {
+                self
+            },
+            Op::ZeroRange(at, len, _) if (0..len as isize).any(|i| base(at + i)) => zero(true),
+            Op::MoveRange(first, last, distance, _) => {
+                let within = |at: isize| (first.min(last)..=first.max(last)).any(|cell| base(cell + at));
+                if within(distance) {
+                    zero(false)
+                } else if within(0) {
+                    zero(true)
+                } else {
+                    self
+                }
+            },
+            Op::MulAdd(_, ref targets, at, _) => if base(at) {
+                zero(true)
+            } else if targets.iter().any(|&(target, _, _)| base(at + target)) {
+                zero(false)
+            } else {
+                self
+            },
+
+            // Loops leave their cell zero, but may write to any other cell
+            Op::Routine(_, true, _) | Op::If(..) if balanced => zero(offset == 0),
+            Op::Routine(..) | Op::If(..) => lost,
+            Op::Hang(_) if offset == 0 => zero(true),
+            Op::DivMod(..) | Op::Scan(..) | Op::Load(..) => lost,
+
+            // Other operations don't write the cell
+            _ => self,
+        }
+    }
+
+    /// Check whether the body ends at the cell it started at.
+    fn balanced(self) -> bool {
+        self.offset == Some(0)
+    }
+}
+
+
+
+/// Lower loops that run at most once in the given program into routines
+/// that run once.
+///
+/// The whole program should be given to `program`.
+/// Nested routines are taken apart and rebuilt bottom-up using an explicit
+/// stack, so what is known about nested loops is known when analysing the
+/// loop containing them.
+///
+/// The number of lowered loops is returned.
+pub fn optimize_if(program: &mut Op) -> usize {
+    let mut changes = 0;
+
+    // The routines being rebuilt, with the operations left to process, the
+    // processed operations, the condition, span and what is known about them
+    // The condition is `None` for routines that run once
+    let mut stack = match *program {
+        Op::Routine(ref mut ops, cond, span) =>
+            vec![(mem::take(ops).into_iter(), vec![], Some(cond), span, Known::start())],
+        _ => return 0,
+    };
+
+    loop {
+        let next = stack.last_mut().unwrap().0.next();
+        match next {
+            // Take nested routines apart, keep other operations
+            Some(mut op) => match op {
+                Op::Routine(ref mut ops, cond, span) =>
+                    stack.push((mem::take(ops).into_iter(), vec![], Some(cond), span, Known::start())),
+                Op::If(ref mut ops, span) =>
+                    stack.push((mem::take(ops).into_iter(), vec![], None, span, Known::start())),
+                _ => {
+                    let outer = stack.last_mut().unwrap();
+                    outer.4 = outer.4.after(&op, true);
+                    outer.1.push(op);
+                },
+            },
+
+            // Lower finished loops leaving their cell zero
+            None => {
+                let (_, ops, cond, span, known) = stack.pop().unwrap();
+                let op = match cond {
+                    Some(true) if known.balanced() && known.zero => {
+                        changes += 1;
+                        Op::If(ops, span)
+                    },
+                    Some(cond) => Op::Routine(ops, cond, span),
+                    None => Op::If(ops, span),
+                };
+                match stack.last_mut() {
+                    Some(outer) => {
+                        outer.4 = outer.4.after(&op, known.balanced());
+                        outer.1.push(op);
+                    },
+                    None => {
+                        *program = op;
+                        return changes;
+                    },
+                }
+            },
+        }
+    }
+}
+
+
+
+/// Test whether loops running at most once are lowered, and whether programs
+/// still produce the same output.
+#[test]
+fn test_if() {
+    use super::super::super::{bf, Interpreter, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.pipeline = Pipeline::with_passes(&["zero", "add_and_zero", "if"], false).unwrap();
+    let lowered = |options: &Options, prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), options).unwrap();
+        options.pipeline.run(&mut program).last().unwrap().1
+    };
+
+    assert_eq!(lowered(&options, "[>+<[-]][.[->+<]][>[-]<[-]>>+<<]"), 3);
+    assert_eq!(lowered(&options, "[[[-]]]"), 2);
+    assert_eq!(lowered(&options, "[>[-.]<[-]]"), 1);
+    assert_eq!(lowered(&options, "[-][[-]+][>[-]][[-]>][>[>]<<[-]]"), 0);
+    assert_eq!(lowered(&options, "[[-]>[-]<][>[<-]<[-]>]"), 1);
+
+    options.pipeline = Pipeline::level(2);
+    options.inline_input = true;
+    options.input_fallback = false;
+    let prog = ",[>++++++++[<++++++++>-]<[->+<]]>.,[<[-]+>[-]]<.!\u{1}\u{2}";
+    assert_eq!(bf(&mut prog.as_bytes(), &options).unwrap(), "A\u{1}");
+}
@@ -0,0 +1,20 @@
+mod cleanup;
+mod dead_loop;
+mod div_mod;
+mod if_loop;
+mod offset;
+mod partial_eval;
+mod print;
+mod range;
+mod set;
+
+// Reexport
+pub use self::cleanup::optimize_cleanup;
+pub use self::dead_loop::optimize_dead_loops;
+pub use self::div_mod::optimize_div_mod;
+pub use self::if_loop::optimize_if;
+pub use self::offset::optimize_offsets;
+pub use self::partial_eval::{PartialEval, DEFAULT_BUDGET};
+pub use self::print::optimize_print;
+pub use self::range::optimize_ranges;
+pub use self::set::optimize_set;
@@ -0,0 +1,211 @@
+//! # Offset optimization
+//! This defers moving the memory pointer until a loop boundary, by accessing
+//! memory cells at an offset relative to the pointer instead.
+//!
+//! Straight-line code between loop boundaries is collapsed into operations
+//! accessing cells at an offset, followed by a single seek. The pointer must
+//! still be moved before a loop or scan, as they check the current cell, and
+//! at the end of a loop body. It is also moved before a debug
+//! operation, to dump the real memory state.
+//!
+//! Seeking down past the first cell clamps the pointer to it, so a seek is
+//! only deferred while the pointer provably stays at or after the first
+//! cell. Where the pointer position is known, from the start of the program
+//! up to the first loop, clamping is applied to the deferred offset instead.
+//! Elsewhere the pointer is moved at once when the deferred offset would
+//! become negative.
+//!
+//! # Optimization requirements
+//! - A sequence of operations, seeking the memory pointer.
+//!
+//! # Example routines
+//! - `>+>+>+<<<`
+//!     - Increment the cells at offset 1, 2 and 3, without seeking
+//! - `>>[-]>+<`
+//!     - Zero the cell at offset 2, increment the cell at offset 3, seek 2
+//! - `[>]>+<<+`
+//!     - After the loop, increment the cell at offset 1, seek -1 as the
+//!       pointer may be clamped, increment the current cell
+
+
+
+use std::mem;
+
+use super::super::super::{Op, Span};
+
+
+
+/// Defer moving the memory pointer in the given program.
+///
+/// The whole interpreted program should be given to `program`, the memory
+/// access operations in it must all have a zero offset.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of changes is returned, counting removed seeks and operations
+/// moved to another offset.
+pub fn optimize_offsets(program: &mut Op) -> usize {
+    let mut changes = 0;
+    // The pointer starts at the first cell, if this is the whole program
+    let mut stack: Vec<(&mut Vec<Op>, Option<isize>)> = match *program {
+        Op::Routine(ref mut ops, cond, _) => vec![(ops, if cond { None } else { Some(0) })],
+        _ => return 0,
+    };
+
+    while let Some((ops, mut pointer)) = stack.pop() {
+        // The pointer offset that is deferred, and the seeks it came from
+        let mut offset = 0;
+        let mut seek: Option<Span> = None;
+
+        for mut op in mem::take(ops) {
+            match op {
+                // Defer seeking, clamp to the first cell if the pointer is
+                // known, or seek at once if it may be clamped
+                Op::Seek(amount, span) => {
+                    changes += 1;
+                    seek = Some(seek.map_or(span, |seek| seek.join(span)));
+                    match pointer {
+                        Some(pointer) => offset = (offset + amount).max(-pointer),
+                        None => {
+                            offset += amount;
+                            if offset < 0 {
+                                changes -= flush(ops, &mut offset, &mut seek, &mut pointer);
+                            }
+                        },
+                    }
+                    continue;
+                },
+
+                // Access memory at the deferred offset
+                Op::Inc(_, ref mut at, _)
+                    | Op::Input(ref mut at, _)
+                    | Op::Output(ref mut at, _)
+                    | Op::Zero(ref mut at, _)
+                    | Op::ZeroRange(ref mut at, _, _)
+                    | Op::Set(_, ref mut at, _)
+                    | Op::AddAndZero(_, ref mut at, _)
+                    | Op::MulAdd(_, _, ref mut at, _) => if offset != 0 {
+                        *at += offset;
+                        changes += 1;
+                    },
+
+                Op::MoveRange(ref mut first, ref mut last, _, _) => if offset != 0 {
+                    *first += offset;
+                    *last += offset;
+                    changes += 1;
+                },
+
+                // Printing literal bytes doesn't access memory
+                Op::Print(..) => {},
+
+                // Seek before debugging and hangs
+                Op::Hang(..) | Op::Debug(..) =>
+                    changes -= flush(ops, &mut offset, &mut seek, &mut pointer),
+
+                // Seek before loops, the pointer is unknown after them
+                Op::Routine(..) | Op::If(..) | Op::DivMod(..) | Op::Scan(..) => {
+                    changes -= flush(ops, &mut offset, &mut seek, &mut pointer);
+                    pointer = None;
+                },
+
+                // Seek before loading memory, which sets the pointer
+                Op::Load(_, at, _) => {
+                    changes -= flush(ops, &mut offset, &mut seek, &mut pointer);
+                    pointer = Some(at as isize);
+                },
+            }
+            ops.push(op);
+        }
+
+        // Seek at the end of the sequence
+        changes -= flush(ops, &mut offset, &mut seek, &mut pointer);
+
+        // Process the routines, the pointer is unknown in them
+        for op in ops {
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push((body, None));
+            }
+        }
+    }
+
+    changes
+}
+
+/// Commit the deferred `offset` as seek to `ops` if it isn't zero,
+/// and reset it.
+/// The span of the seeks it came from should be given to `seek`.
+/// The known `pointer` position is moved by the offset.
+///
+/// The number of committed seeks is returned.
+fn flush(
+    ops: &mut Vec<Op>,
+    offset: &mut isize,
+    seek: &mut Option<Span>,
+    pointer: &mut Option<isize>,
+) -> usize {
+    let offset = mem::replace(offset, 0);
+    if let Some(ref mut pointer) = *pointer {
+        *pointer += offset;
+    }
+    match seek.take() {
+        Some(span) if offset != 0 => {
+            ops.push(Op::Seek(offset, span));
+            1
+        },
+        _ => 0,
+    }
+}
+
+
+
+/// Test whether straight-line code is collapsed into a single seek, and
+/// whether programs still produce the same output.
+#[test]
+fn test_offsets() {
+    use super::super::super::{bf, Interpreter, Options};
+
+    let options = Options::default(true, false, false, false);
+    let mut program = Interpreter::interpret(">+>+>+<<<[->.<]>>.".bytes(), &options).unwrap();
+    assert_eq!(optimize_offsets(&mut program), 11);
+    assert_eq!(optimize_offsets(&mut program), 0);
+    let ops = match program {
+        Op::Routine(ref ops, false, _) => ops,
+        _ => panic!("expected a top level routine"),
+    };
+    match ops[..] {
+        [Op::Inc(1, 1, _), Op::Inc(1, 2, _), Op::Inc(1, 3, _), Op::Routine(ref body, true, _), Op::Output(2, _), Op::Seek(2, _)] =>
+            match body[..] {
+                [Op::Inc(-1, 0, _), Op::Output(1, _)] => {},
+                _ => panic!("unexpected loop body: {:?}", body),
+            },
+        _ => panic!("unexpected operations: {:?}", ops),
+    }
+
+    let prog = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+    assert_eq!(bf(&mut prog.as_bytes(), &options).unwrap(), "Hello World!\n");
+}
+
+/// Test whether deferred seeks are clamped to the first cell like the
+/// pointer, so programs seeking down past it produce the same output.
+#[test]
+fn test_offsets_clamp() {
+    use super::super::super::{bf, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.inline_input = true;
+    options.input_fallback = false;
+    let progs = [
+        "<+>.".to_string(),
+        "++<<+>>+.".into(),
+        ">>>>+[<<+,>-]".into(),
+        ">,.<<.+>>[<>+[[++]+>.-]-<]".into(),
+        format!("{}.+->-[<<+]--[[+,<<->]>,<>]>>+", ">".repeat(40)),
+    ];
+    for prog in &progs {
+        options.pipeline = Pipeline::level(0);
+        let original = bf(&mut prog.as_bytes(), &options).unwrap();
+        for level in 1..4 {
+            options.pipeline = Pipeline::level(level);
+            assert_eq!(bf(&mut prog.as_bytes(), &options).unwrap(), original, "{} at level {}", prog, level);
+        }
+    }
+}
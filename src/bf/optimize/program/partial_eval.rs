@@ -0,0 +1,245 @@
+//! # Partial evaluation
+//! This evaluates the start of a program at compile time, up to the first
+//! operation that depends on user input.
+//!
+//! Memory is known to be zero at program start, so operations that don't
+//! read input always behave the same. Many programs do a lot of setup work
+//! before reading input, or don't read input at all. The evaluated operations
+//! are replaced by the output they produced, and the memory state they
+//! leave.
+//!
+//! Evaluation is limited to a budget of steps, so programs that run for a
+//! long time, or never end, don't stall optimization. A top level loop that
+//! doesn't finish within the budget isn't evaluated at all.
+//!
+//! # Optimization requirements
+//! - Top level operations at the start of the program.
+//! - The operations must not read input, or dump memory for debugging.
+//! - The operations must not hang, or access memory past its end.
+//! - The operations must finish within the step budget.
+//!
+//! # Example routines
+//! - `++++++++[>++++++++<-]>+.>,.`
+//!     - Print `A`
+//!     - Load memory cells `0, 65`, and point to the third cell
+//!     - Keep the input and output
+
+
+
+use super::super::super::{Memory, Op};
+use super::super::pass::Pass;
+
+
+
+/// The default number of steps to evaluate programs for.
+pub const DEFAULT_BUDGET: usize = 1_000_000;
+
+
+
+/// A pass that evaluates the start of a program at compile time.
+pub struct PartialEval {
+    /// The maximum number of steps to evaluate.
+    budget: usize,
+}
+
+impl PartialEval {
+    /// Create a new pass, evaluating at most `budget` steps.
+    pub fn new(budget: usize) -> PartialEval {
+        PartialEval {
+            budget,
+        }
+    }
+}
+
+impl Pass for PartialEval {
+    fn name(&self) -> &'static str {
+        "partial_eval"
+    }
+
+    /// Evaluate the start of the program, and replace the evaluated
+    /// operations with a print and load operation.
+    ///
+    /// The number of evaluated top level operations is returned.
+    fn run(&self, program: &mut Op) -> usize {
+        let ops = match *program {
+            Op::Routine(ref mut ops, false, _) => ops,
+            _ => return 0,
+        };
+
+        // Evaluate top level operations until one can't be evaluated
+        let mut memory = Memory::new();
+        let mut output = vec![];
+        let mut budget = self.budget;
+        let mut count = 0;
+        for op in ops.iter() {
+            // Routines may fail halfway, keep the state to restore
+            let snapshot = match *op {
+                Op::Routine(..) | Op::If(..) => Some((memory.clone(), output.len())),
+                _ => None,
+            };
+
+            if !evaluate(op, &mut memory, &mut output, &mut budget) {
+                if let Some((snapshot, len)) = snapshot {
+                    memory = snapshot;
+                    output.truncate(len);
+                }
+                break;
+            }
+            count += 1;
+        }
+
+        // Don't fold a start that was folded before again
+        let folded = ops.iter()
+            .take_while(|op| matches!(**op, Op::Print(..) | Op::Load(..)))
+            .count();
+        if count <= folded {
+            return 0;
+        }
+
+        // Replace the evaluated operations
+        let span = ops[1..count].iter().fold(ops[0].span(), |span, op| span.join(op.span()));
+        let mut start = vec![];
+        if !output.is_empty() {
+            start.push(Op::Print(output, span));
+        }
+        start.push(Op::Load(memory.image().to_vec(), memory.pointer(), span));
+        ops.splice(..count, start);
+
+        count
+    }
+}
+
+/// Evaluate the given operation on the given `memory`, pushing output to
+/// `output`.
+/// Nested routines are evaluated using an explicit stack, each operation and
+/// loop condition check takes a step from the `budget`.
+///
+/// `false` is returned if the operation can't be evaluated, leaving memory
+/// and output in an undefined state.
+fn evaluate(op: &Op, memory: &mut Memory, output: &mut Vec<u8>, budget: &mut usize) -> bool {
+    // The routines being evaluated, with their condition and the index of
+    // the next operation to evaluate in them
+    let mut stack: Vec<(&[Op], bool, usize)> = vec![];
+    let mut op = op;
+
+    loop {
+        if *budget == 0 {
+            return false;
+        }
+        *budget -= 1;
+
+        // Operations accessing memory past the end aren't evaluated
+        if !op.fits(memory) {
+            return false;
+        }
+
+        match *op {
+            Op::Routine(ref ops, cond, _) => if !cond || !memory.zero() {
+                stack.push((ops, cond, 0));
+            },
+            Op::If(ref ops, _) => if !memory.zero() {
+                stack.push((ops, false, 0));
+            },
+            Op::Seek(amount, _) => memory.seek(amount),
+            Op::Inc(amount, offset, _) => memory.inc(offset, amount),
+            Op::Zero(offset, _) => memory.set_zero(offset),
+            Op::ZeroRange(offset, len, _) => memory.zero_range(offset, len),
+            Op::Set(value, offset, _) => memory.write(offset, value),
+            Op::Output(offset, _) => output.push(memory.read(offset)),
+            Op::AddAndZero(ref targets, offset, _) => memory.copy_zero(offset, targets),
+            Op::MoveRange(first, last, distance, _) => memory.move_range(first, last, distance),
+            Op::MulAdd(scale, ref targets, offset, _) => memory.mul_add(offset, scale, targets),
+            Op::DivMod(divisor, _, _) => if !memory.contains(divisor + 4) || !memory.div_mod(divisor) {
+                return false;
+            },
+            Op::Scan(stride, _) => if !memory.scan(stride) {
+                return false;
+            },
+            Op::Hang(_) => if !memory.zero() {
+                return false;
+            },
+            Op::Print(ref bytes, _) => output.extend_from_slice(bytes),
+            Op::Load(ref image, pointer, _) => memory.load(image, pointer),
+
+            // Input and debugging can't be evaluated ahead of time
+            Op::Input(..) | Op::Debug(..) => return false,
+        }
+
+        // Find the next operation to evaluate, leave finished routines
+        op = loop {
+            match stack.last_mut() {
+                Some(&mut (ops, cond, ref mut index)) => {
+                    if let Some(next) = ops.get(*index) {
+                        *index += 1;
+                        break next;
+                    }
+
+                    // Loop the routine again if it is conditional, and the
+                    // current memory cell value isn't zero
+                    if cond {
+                        if *budget == 0 || !memory.contains(0) {
+                            return false;
+                        }
+                        *budget -= 1;
+                        if !memory.zero() {
+                            *index = 0;
+                            continue;
+                        }
+                    }
+                },
+                None => return true,
+            }
+            stack.pop();
+        };
+    }
+}
+
+
+
+/// Test whether the program start is folded up to the first input, and
+/// whether programs still produce the same output.
+#[test]
+fn test_partial_eval() {
+    use super::super::super::{bf, Interpreter, Options, Pipeline};
+
+    let options = Options::default(true, false, false, false);
+    let fold = |prog: &str, budget: usize| {
+        let mut program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+        let count = PartialEval::new(budget).run(&mut program);
+        let ops = match program {
+            Op::Routine(ref ops, false, _) => ops.iter()
+                .map(|op| match *op {
+                    Op::Print(ref bytes, _) => format!("print {:?}", String::from_utf8_lossy(bytes)),
+                    Op::Load(ref image, pointer, _) => format!("load {:?} @{}", image, pointer),
+                    Op::Input(..) => "input".into(),
+                    _ => "other".into(),
+                })
+                .collect::<Vec<_>>(),
+            _ => panic!("expected a top level routine"),
+        };
+        (count, ops)
+    };
+
+    let prog = "++++++++[>++++++++<-]>+.>,.";
+    assert_eq!(
+        fold(prog, 1000),
+        (6, vec!["print \"A\"".into(), "load [0, 65] @2".into(), "input".into(), "other".into()]),
+    );
+
+    // Loops not finishing within the budget aren't evaluated
+    let (count, ops) = fold(prog, 10);
+    assert_eq!((count, &ops[0]), (1, &"load [8] @0".to_string()));
+    assert_eq!(fold(",+.", 1000).0, 0);
+
+    // Folding again doesn't change anything
+    let mut program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+    let pass = PartialEval::new(DEFAULT_BUDGET);
+    assert_eq!(pass.run(&mut program), 6);
+    assert_eq!(pass.run(&mut program), 0);
+
+    let mut options = Options::default(true, false, false, false);
+    options.inline_input = true;
+    options.input_fallback = false;
+    options.pipeline = Pipeline::level(3);
+    assert_eq!(bf(&mut format!("{}!b", prog).as_bytes(), &options).unwrap(), "Ab");
+}
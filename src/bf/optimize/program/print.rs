@@ -0,0 +1,251 @@
+//! # Print optimization
+//! This replaces outputs of cells with a known value by printing literal
+//! bytes, and combines them into a single print.
+//!
+//! Programs often build the bytes they output in straight-line code, such as
+//! `+++.+.+++++++.`, outputting one byte at a time. The cell values are
+//! tracked through straight-line code, so the output of such code is known.
+//! The outputs are replaced by a single print of all bytes, placed at the
+//! first output. Operations changing memory are kept, so memory still ends
+//! up in the same state.
+//!
+//! Memory accessed at negative offsets may be clamped to the first cell, so
+//! what is known about memory is forgotten when that happens.
+//!
+//! This should run after the offset optimization, so cells accessed between
+//! seeks have distinct offsets.
+//!
+//! # Optimization requirements
+//! - Outputs of cells with a known value, only separated by operations that
+//!   change memory and other such outputs.
+//! - Cells are known after zeroing or setting them, and at program start.
+//!
+//! # Example routines
+//! - `+++.+.+++++++.`
+//!     - Print the bytes 3, 4 and 11, increment the current cell to 11
+//! - `[-]>[-]<++++++++[>++++++++<-]>+.+.`
+//!     - Print `AB`, set the current cell to 66
+
+
+
+use std::collections::HashMap;
+use std::mem;
+
+use super::super::super::Op;
+
+
+
+/// What is known about memory cell values, at some point in straight-line
+/// code.
+struct Known {
+    /// The values of written cells at non-negative offsets, if known.
+    cells: HashMap<isize, Option<u8>>,
+
+    /// Whether the cells at non-negative offsets that aren't written are
+    /// known to be zero.
+    zero: bool,
+}
+
+impl Known {
+    /// Get the known value of the cell at offset `at`.
+    fn get(&self, at: isize) -> Option<u8> {
+        match self.cells.get(&at) {
+            Some(&value) => value,
+            None if at >= 0 && self.zero => Some(0),
+            None => None,
+        }
+    }
+
+    /// Set the value of the cell at offset `at`, if it is known.
+    /// Writing a cell at a negative offset forgets everything.
+    fn set(&mut self, at: isize, value: Option<u8>) {
+        if at < 0 {
+            self.clear();
+        } else {
+            self.cells.insert(at, value);
+        }
+    }
+
+    /// Forget everything that is known.
+    fn clear(&mut self) {
+        self.cells.clear();
+        self.zero = false;
+    }
+
+    /// Update what is known after running the given operation.
+    fn after(&mut self, op: &Op) {
+        match *op {
+            Op::Seek(amount, _) if amount >= 0 => {
+                self.cells = self.cells.drain()
+                    .filter(|&(at, _)| at >= amount)
+                    .map(|(at, value)| (at - amount, value))
+                    .collect();
+            },
+            Op::Inc(amount, at, _) => {
+                let value = self.get(at).map(|value| value.wrapping_add(amount as u8));
+                self.set(at, value);
+            },
+            Op::Zero(at, _) => self.set(at, Some(0)),
+            Op::Set(value, at, _) => self.set(at, Some(value)),
+            Op::Input(at, _) => self.set(at, None),
+            Op::ZeroRange(at, len, _) => for i in 0..len as isize {
+                self.set(at + i, Some(0));
+            },
+            Op::AddAndZero(ref targets, at, _) if at >= 0 => {
+                let value = self.get(at);
+                for &(target, factor) in targets {
+                    let sum = value.and_then(|value| {
+                        self.get(at + target).map(|cell| cell.wrapping_add(value.wrapping_mul(factor)))
+                    });
+                    self.set(at + target, sum);
+                }
+                self.set(at, Some(0));
+            },
+            Op::MoveRange(first, last, distance, _) if first.min(last) + distance.min(0) >= 0 => {
+                let (low, high) = (first.min(last), first.max(last));
+                let values: Vec<_> = (low..=high).map(|at| self.get(at)).collect();
+                for at in low..=high {
+                    self.set(at, Some(0));
+                }
+                for (at, value) in (low..=high).zip(values) {
+                    let sum = value.and_then(|value| {
+                        self.get(at + distance).map(|cell| cell.wrapping_add(value))
+                    });
+                    self.set(at + distance, sum);
+                }
+            },
+            Op::MulAdd(_, ref targets, at, _) if at >= 0 => {
+                for &(target, _, _) in targets {
+                    self.set(at + target, None);
+                }
+                self.set(at, Some(0));
+            },
+
+            // Outputs and prints don't change memory
+            Op::Output(..) | Op::Print(..) | Op::Debug(..) => {},
+
+            // Nothing is known after loops, seeking down or loading memory
+            _ => self.clear(),
+        }
+    }
+}
+
+
+
+/// Replace outputs of cells with a known value in the given program by
+/// printing literal bytes, combining adjacent prints.
+///
+/// The whole program should be given to `program`.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of changes is returned, counting replaced outputs and
+/// combined prints.
+pub fn optimize_print(program: &mut Op) -> usize {
+    let mut changes = 0;
+
+    // All memory is zero at the start of the program
+    let mut stack: Vec<(&mut Vec<Op>, bool)> = match *program {
+        Op::Routine(ref mut ops, cond, _) => vec![(ops, !cond)],
+        _ => return 0,
+    };
+
+    while let Some((ops, zero)) = stack.pop() {
+        let mut known = Known {
+            cells: HashMap::new(),
+            zero,
+        };
+
+        // The index of the print the current outputs are combined into
+        let mut print: Option<usize> = None;
+
+        for op in mem::take(ops) {
+            let bytes = match op {
+                Op::Output(at, _) => known.get(at).map(|value| vec![value]),
+                Op::Print(ref bytes, _) => Some(bytes.clone()),
+                _ => None,
+            };
+
+            match (bytes, print) {
+                // Combine with the current print
+                (Some(mut bytes), Some(index)) => {
+                    if let Op::Print(ref mut printed, ref mut span) = ops[index] {
+                        printed.append(&mut bytes);
+                        *span = span.join(op.span());
+                    }
+                    changes += 1;
+                },
+
+                // Start a print
+                (Some(bytes), None) => {
+                    if let Op::Output(..) = op {
+                        changes += 1;
+                    }
+                    print = Some(ops.len());
+                    ops.push(Op::Print(bytes, op.span()));
+                },
+
+                // Only operations changing memory may be passed
+                (None, _) => {
+                    if !op.changes_memory_only() {
+                        print = None;
+                    }
+                    known.after(&op);
+                    ops.push(op);
+                },
+            }
+        }
+
+        // Process the routines
+        for op in ops {
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push((body, false));
+            }
+        }
+    }
+
+    changes
+}
+
+
+
+/// Test whether outputs of known cell values are combined into prints, and
+/// whether programs still produce the same output.
+#[test]
+fn test_print() {
+    use super::super::super::{bf, Interpreter, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.pipeline = Pipeline::with_passes(&["zero", "add_and_zero", "offsets", "set", "print"], false).unwrap();
+    let optimize = |options: &Options, prog: &str| -> Vec<String> {
+        let mut program = Interpreter::interpret(prog.bytes(), options).unwrap();
+        options.pipeline.run(&mut program);
+        match program {
+            Op::Routine(ref ops, false, _) => ops.iter()
+                .map(|op| match *op {
+                    Op::Print(ref bytes, _) => format!("print {:?}", bytes),
+                    Op::Output(at, _) => format!("output @{}", at),
+                    Op::Routine(..) => "loop".into(),
+                    _ => "memory".into(),
+                })
+                .filter(|op| op != "memory")
+                .collect(),
+            _ => panic!("expected a top level routine"),
+        }
+    };
+
+    assert_eq!(optimize(&options, "+++.+.+++++++."), vec!["print [3, 4, 11]"]);
+    assert_eq!(optimize(&options, "+.>++.<<.>>>,."), vec!["print [1, 2, 1]", "output @3"]);
+    assert_eq!(optimize(&options, "+.[.-]>+.>[-]+.-."), vec!["print [1]", "loop", "output @1", "print [1, 0]"]);
+
+    // Outputs in loops are only known after zeroing or setting cells
+    options.pipeline = Pipeline::level(2);
+    let prog = ",[>[-]<[-]>>[-]<++++++++[>++++++++<-]>+.+.<,]>.!\u{1}\u{1}";
+    let mut program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+    let changes = options.pipeline.run(&mut program);
+    assert!(changes.contains(&("print", 2)));
+    options.inline_input = true;
+    options.input_fallback = false;
+    let optimized = bf(&mut prog.as_bytes(), &options).unwrap();
+    options.pipeline = Pipeline::level(0);
+    assert_eq!(optimized, bf(&mut prog.as_bytes(), &options).unwrap());
+}
@@ -0,0 +1,179 @@
+//! # Range optimization
+//! This combines operations on a row of adjacent memory cells into a single
+//! operation on the whole range of cells.
+//!
+//! Clearing a buffer, or moving it to another place in memory, is done one
+//! cell at a time by brainfuck programs. Once loops are optimized and seeks
+//! are deferred, such code is a run of zero or move operations with offsets
+//! following each other. The run is replaced by a single range operation,
+//! which is executed as slice operation on the memory cells.
+//!
+//! Moves are only combined if the range moved from and the range moved to
+//! don't overlap, or if the cells are moved starting at the end of the range
+//! nearest to where they are moved to. Otherwise a cell moved to is moved
+//! again, which isn't a block move.
+//!
+//! This should run after the offset optimization.
+//!
+//! # Optimization requirements
+//! - At least two adjacent zero operations, or at least two adjacent move
+//!   operations with the same distance.
+//! - The offsets of the operations follow each other, counting up or down.
+//!
+//! # Example routines
+//! - `[-]>[-]>[-]>[-]`
+//!     - Zero the current cell and the three cells after it
+//! - `[->>>>+<<<<]>[->>>>+<<<<]>[->>>>+<<<<]`
+//!     - Move the current cell and the two cells after it four cells up
+//! - `>>[->+<]<[->+<]<[->+<]`
+//!     - Shift the cell at offset 2 and the two cells before it one cell up
+
+
+
+use std::mem;
+
+use super::super::super::Op;
+
+
+
+/// Combine runs of zero and move operations on adjacent memory cells in the
+/// given program into range operations.
+///
+/// The whole program should be given to `program`.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of combined runs is returned.
+pub fn optimize_ranges(program: &mut Op) -> usize {
+    let mut changes = 0;
+    let mut stack: Vec<&mut Vec<Op>> = match *program {
+        Op::Routine(ref mut ops, _, _) => vec![ops],
+        _ => return 0,
+    };
+
+    while let Some(ops) = stack.pop() {
+        let mut iter = mem::take(ops).into_iter().peekable();
+        while let Some(op) = iter.next() {
+            let (distance, first) = match cell(&op) {
+                Some(cell) => cell,
+                None => {
+                    ops.push(op);
+                    continue;
+                },
+            };
+
+            // Collect the run of operations on following cells
+            let mut run = vec![op];
+            let mut last = first;
+            let mut direction = None;
+            while let Some((next_distance, at)) = iter.peek().and_then(cell) {
+                let step = at - last;
+                let follows = next_distance == distance
+                    && (step == 1 || step == -1)
+                    && direction.unwrap_or(step) == step
+                    && match distance {
+                        Some(distance) => step == -distance.signum() || run.len() < distance.unsigned_abs(),
+                        None => true,
+                    };
+                if !follows {
+                    break;
+                }
+                run.push(iter.next().unwrap());
+                last = at;
+                direction = Some(step);
+            }
+
+            if run.len() < 2 {
+                ops.append(&mut run);
+                continue;
+            }
+            changes += 1;
+            let span = run[0].span().join(run[run.len() - 1].span());
+            ops.push(match distance {
+                Some(distance) => Op::MoveRange(first, last, distance, span),
+                None => Op::ZeroRange(first.min(last), run.len(), span),
+            });
+        }
+
+        // Process the routines
+        for op in ops {
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push(body);
+            }
+        }
+    }
+
+    changes
+}
+
+/// Get the offset of the cell the given operation zeroes or moves, if it
+/// does.
+/// For moves, the distance the cell is moved is returned as well.
+fn cell(op: &Op) -> Option<(Option<isize>, isize)> {
+    match *op {
+        Op::Zero(at, _) => Some((None, at)),
+        Op::AddAndZero(ref targets, at, _) => match targets[..] {
+            [(distance, 1)] if distance != 0 => Some((Some(distance), at)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+
+
+/// Test whether runs of operations on adjacent cells are combined, and
+/// whether they change memory the same as the original operations.
+#[test]
+fn test_ranges() {
+    use super::super::super::{Input, Interpreter, Memory, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    let interpret = |options: &Options, prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), options).unwrap();
+        let changes = options.pipeline.run(&mut program).last().unwrap().1;
+        (program, changes)
+    };
+    let run = |options: &Options, program: &Op, pointer: isize| {
+        let mut memory = Memory::new();
+        for i in 0..16 {
+            memory.write(i, (i * 37 + 11) as u8);
+        }
+        memory.seek(pointer);
+        program.execute(&mut memory, options, &mut Input::new(vec![], false), &mut vec![]).unwrap();
+        (memory.pointer(), memory.window(24).1.to_vec())
+    };
+
+    let progs = [
+        ("[-]>[-]>[-]>[-]", 1),
+        ("<[-]>[-]>>[-]<[-]", 2),
+        ("[->>>>+<<<<]>[->>>>+<<<<]>[->>>>+<<<<]", 1),
+        ("[-<<<+>>>]>[-<<<+>>>]>[-<<<+>>>]", 1),
+        ("[->+<]<[->+<]<[->+<]", 0),
+        (">>[->+<]<[->+<]<[->+<]", 1),
+        ("[-<+>]>[-<+>]>[-<+>]", 1),
+        ("[->+<]>[->+<]>[->+<]", 0),
+        ("[->>+<<]>[->>+<<]>[->>+<<]", 1),
+        ("[->>+<<]>[->>>+<<<]>[-]>[-]", 1),
+    ];
+    for &(prog, runs) in &progs {
+        // The pointer is unknown after a loop, so the programs may run at any
+        // pointer position, and seeks down may be clamped
+        let prog = &format!("[[-]]{}", prog);
+        options.pipeline = Pipeline::with_passes(&["zero", "add_and_zero", "offsets", "ranges"], false).unwrap();
+        let (optimized, changes) = interpret(&options, prog);
+        assert_eq!(changes, runs, "{}", prog);
+        options.pipeline = Pipeline::with_passes(&["zero", "add_and_zero", "offsets"], false).unwrap();
+        let (original, _) = interpret(&options, prog);
+
+        // Cells before the first are clamped to it near the start of memory
+        for pointer in 0..8 {
+            assert_eq!(
+                run(&options, &optimized, pointer),
+                run(&options, &original, pointer),
+                "{} at {}",
+                prog,
+                pointer,
+            );
+        }
+    }
+}
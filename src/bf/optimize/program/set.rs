@@ -0,0 +1,168 @@
+//! # Set optimization
+//! This merges operations writing the same memory cell within straight-line
+//! code, so a cell is only written once.
+//!
+//! Zeroing a cell followed by increments sets the cell to a known value.
+//! Increments followed by zeroing or setting a cell have no effect, just
+//! like zeroing or setting a cell repeatedly.
+//! Operations can be merged across operations accessing other cells.
+//!
+//! This should run after the offset optimization, so cells accessed between
+//! seeks have distinct offsets.
+//!
+//! # Optimization requirements
+//! - Increment, zero or set operations on the same cell, only separated by
+//!   operations on other cells.
+//!
+//! # Example routines
+//! - `[-]+++++`
+//!     - Set the current cell to 5
+//! - `+++[-]`
+//!     - Zero the current cell
+//! - `[-]>+<[-]++`
+//!     - Increment the next cell, set the current cell to 2
+
+
+
+use std::mem;
+
+use super::super::super::{Op, Span};
+
+
+
+/// Merge operations writing the same memory cell in the given program.
+///
+/// The whole program should be given to `program`.
+/// Nested routines are processed using an explicit stack.
+///
+/// The number of changes is returned, counting merged and removed
+/// operations.
+pub fn optimize_set(program: &mut Op) -> usize {
+    let mut changes = 0;
+    let mut stack: Vec<&mut Vec<Op>> = match *program {
+        Op::Routine(ref mut ops, _, _) => vec![ops],
+        _ => return 0,
+    };
+
+    while let Some(ops) = stack.pop() {
+        // The index the current straight-line code starts at
+        let mut block = 0;
+
+        for op in mem::take(ops) {
+            match op {
+                // Merge with an earlier write to the same cell
+                Op::Inc(_, at, _) | Op::Zero(at, _) | Op::Set(_, at, _) => {
+                    let merged = find_write(&ops[block..], at)
+                        .map(|i| merge(&mut ops[block + i], &op))
+                        .unwrap_or(false);
+                    if merged {
+                        changes += 1;
+                    } else {
+                        ops.push(op);
+                    }
+                },
+
+                // Reading a cell or printing doesn't end straight-line code
+                Op::Input(..) | Op::Output(..) | Op::Print(..) => ops.push(op),
+
+                // Other operations end straight-line code
+                _ => {
+                    ops.push(op);
+                    block = ops.len();
+                },
+            }
+        }
+
+        // Remove increments that cancelled out
+        let before = ops.len();
+        ops.retain(|op| !matches!(*op, Op::Inc(0, _, _)));
+        changes += before - ops.len();
+
+        // Process the routines
+        for op in ops {
+            if let Op::Routine(ref mut body, _, _) | Op::If(ref mut body, _) = *op {
+                stack.push(body);
+            }
+        }
+    }
+
+    changes
+}
+
+/// Find the last operation writing the cell at offset `at` in the given
+/// straight-line code, that a following write may be merged with.
+/// `None` is returned if the cell is read after the last write.
+fn find_write(ops: &[Op], at: isize) -> Option<usize> {
+    for (i, op) in ops.iter().enumerate().rev() {
+        match *op {
+            Op::Inc(_, offset, _) | Op::Zero(offset, _) | Op::Set(_, offset, _)
+                if offset == at => return Some(i),
+            Op::Input(offset, _) | Op::Output(offset, _) if offset == at => return None,
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Merge the `next` write into the `prev` write to the same cell.
+/// `true` is returned if the writes could be merged.
+fn merge(prev: &mut Op, next: &Op) -> bool {
+    let span = prev.span().join(next.span());
+    let merged = match (&*prev, next) {
+        (&Op::Inc(a, at, _), &Op::Inc(b, _, _)) => Op::Inc(a + b, at, span),
+        (&Op::Zero(at, _), &Op::Inc(amount, _, _)) => set(amount as u8, at, span),
+        (&Op::Set(value, at, _), &Op::Inc(amount, _, _)) =>
+            set(value.wrapping_add(amount as u8), at, span),
+        (_, &Op::Zero(at, _)) => Op::Zero(at, span),
+        (_, &Op::Set(value, at, _)) => Op::Set(value, at, span),
+        _ => return false,
+    };
+    *prev = merged;
+    true
+}
+
+/// Create an operation setting the cell at offset `at` to `value`.
+/// Setting zero is represented as zero operation.
+fn set(value: u8, at: isize, span: Span) -> Op {
+    if value == 0 {
+        Op::Zero(at, span)
+    } else {
+        Op::Set(value, at, span)
+    }
+}
+
+
+
+/// Test whether writes to the same cell are merged.
+#[test]
+fn test_set() {
+    use super::super::super::{Interpreter, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.pipeline = Pipeline::with_passes(&["zero", "offsets", "set"], false).unwrap();
+    let optimize = |prog: &str| -> Vec<String> {
+        let mut program = Interpreter::interpret(prog.bytes(), &options).unwrap();
+        options.pipeline.run(&mut program);
+        match program {
+            Op::Routine(ref ops, false, _) => ops.iter()
+                .map(|op| match *op {
+                    Op::Seek(amount, _) => format!("seek {}", amount),
+                    Op::Inc(amount, at, _) => format!("inc {} @{}", amount, at),
+                    Op::Zero(at, _) => format!("zero @{}", at),
+                    Op::Set(value, at, _) => format!("set {} @{}", value, at),
+                    Op::Input(at, _) => format!("input @{}", at),
+                    Op::Output(at, _) => format!("output @{}", at),
+                    _ => panic!("unexpected operation: {:?}", op),
+                })
+                .collect(),
+            _ => panic!("expected a top level routine"),
+        }
+    };
+
+    assert_eq!(optimize("[-]+++++"), vec!["set 5 @0"]);
+    assert_eq!(optimize("+++[-]"), vec!["zero @0"]);
+    assert_eq!(optimize("[-]>+<[-]++"), vec!["set 2 @0", "inc 1 @1"]);
+    assert_eq!(optimize("[-]-[-]+++[-]>"), vec!["zero @0", "seek 1"]);
+    assert_eq!(optimize("[-]+.+,+-"), vec!["set 1 @0", "output @0", "inc 1 @0", "input @0"]);
+    assert_eq!(optimize(">+<+>+"), vec!["inc 2 @1", "inc 1 @0", "seek 1"]);
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::super::{Op, Span};
+use super::pass::Pass;
+
+
+
+/// The format to render an optimization report in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Human readable text.
+    Human,
+
+    /// JSON, for processing by other tools.
+    Json,
+}
+
+impl ReportFormat {
+    /// Get the report format with the given `name`, `human` or `json`.
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "human" => Some(ReportFormat::Human),
+            "json" => Some(ReportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+
+
+/// A report of what the passes of an optimization pipeline did to a program.
+///
+/// Each pass run is recorded by comparing the operations in the program
+/// before and after it. Operations that appear are reported as rewrite of
+/// their source range, operations that disappear without replacement are
+/// reported as removed.
+pub struct Report {
+    /// The number of operations in the program before optimizing.
+    pub before: usize,
+
+    /// The number of operations in the program after optimizing.
+    pub after: usize,
+
+    /// What each pass did, in pipeline order.
+    pub passes: Vec<PassReport>,
+}
+
+/// What a single optimization pass did to a program.
+pub struct PassReport {
+    /// The name of the pass.
+    pub name: &'static str,
+
+    /// The number of changes the pass made, over all runs.
+    pub changes: usize,
+
+    /// The source ranges the pass rewrote, in program order for each run.
+    pub rewrites: Vec<Rewrite>,
+}
+
+/// A source range rewritten by an optimization pass.
+pub struct Rewrite {
+    /// The rewritten source range.
+    pub span: Span,
+
+    /// Descriptions of the operations the range was rewritten into.
+    /// If empty, the operations of the range were removed.
+    pub ops: Vec<String>,
+}
+
+impl Report {
+    /// Start a report for passes with the given `names`, that optimize the
+    /// given `program`.
+    pub fn new<I: Iterator<Item = &'static str>>(names: I, program: &Op) -> Report {
+        let before = count(program);
+        Report {
+            before,
+            after: before,
+            passes: names
+                .map(|name| PassReport {
+                    name,
+                    changes: 0,
+                    rewrites: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    /// Run the given `pass` on the `program`, and record what it did as the
+    /// pass at `index` in the pipeline.
+    ///
+    /// The number of changes the pass made is returned.
+    pub fn record(&mut self, index: usize, pass: &dyn Pass, program: &mut Op) -> usize {
+        let before = snapshot(program);
+        let changes = pass.run(program);
+        let after = snapshot(program);
+        self.after = after.len();
+
+        let report = &mut self.passes[index];
+        report.changes += changes;
+        if changes > 0 {
+            report.rewrites.extend(diff(before, after));
+        }
+        changes
+    }
+
+    /// Render this report in the given `format`.
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Human => self.human(),
+            ReportFormat::Json => self.json(),
+        }
+    }
+
+    /// Render this report as human readable text.
+    fn human(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "# Optimization report").unwrap();
+        writeln!(out, "# Operations: {} before, {} after", self.before, self.after).unwrap();
+        for pass in &self.passes {
+            writeln!(out, "# Pass {}: {} changes", pass.name, pass.changes).unwrap();
+            for rewrite in &pass.rewrites {
+                if rewrite.ops.is_empty() {
+                    writeln!(out, "#   {:?}: removed", rewrite.span).unwrap();
+                } else {
+                    writeln!(out, "#   {:?}: {}", rewrite.span, rewrite.ops.join(", ")).unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    /// Render this report as JSON.
+    fn json(&self) -> String {
+        let passes: Vec<String> = self.passes.iter()
+            .map(|pass| {
+                let rewrites: Vec<String> = pass.rewrites.iter()
+                    .map(|rewrite| {
+                        let ops: Vec<String> = rewrite.ops.iter()
+                            .map(|op| format!("\"{}\"", escape(op)))
+                            .collect();
+                        format!(
+                            "{{\"start\":{},\"end\":{},\"ops\":[{}]}}",
+                            position(rewrite.span.start.offset, rewrite.span.start.line, rewrite.span.start.column),
+                            position(rewrite.span.end.offset, rewrite.span.end.line, rewrite.span.end.column),
+                            ops.join(","),
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"name\":\"{}\",\"changes\":{},\"rewrites\":[{}]}}",
+                    pass.name,
+                    pass.changes,
+                    rewrites.join(","),
+                )
+            })
+            .collect();
+        format!(
+            "{{\"before\":{},\"after\":{},\"passes\":[{}]}}\n",
+            self.before,
+            self.after,
+            passes.join(","),
+        )
+    }
+}
+
+
+
+/// Count the operations in the given `program`, excluding the top level
+/// routine.
+fn count(program: &Op) -> usize {
+    snapshot(program).len()
+}
+
+/// Collect the span and description of every operation in the given
+/// `program`, excluding the top level routine, in program order.
+/// Nested routines are walked using an explicit stack.
+fn snapshot(program: &Op) -> Vec<(Span, String)> {
+    let mut ops = vec![];
+    let mut stack: Vec<&[Op]> = match *program {
+        Op::Routine(ref body, _, _) => vec![body],
+        _ => return ops,
+    };
+
+    while let Some(body) = stack.pop() {
+        for op in body {
+            ops.push((op.span(), describe(op)));
+            if let Op::Routine(ref body, _, _) | Op::If(ref body, _) = *op {
+                stack.push(body);
+            }
+        }
+    }
+
+    ops.sort_by_key(|&(span, _)| (span.start.offset, span.end.offset));
+    ops
+}
+
+/// Find the rewrites between the `before` and `after` snapshot of a
+/// program.
+///
+/// Operations only in `after` are grouped by span into rewrites.
+/// Operations only in `before` are reported as removed, unless their span is
+/// covered by a rewrite. Adjacent removed spans are joined.
+fn diff(before: Vec<(Span, String)>, after: Vec<(Span, String)>) -> Vec<Rewrite> {
+    let key = |span: Span, op: String| (span.start.offset, span.end.offset, op);
+    let mut removed: HashMap<_, (Span, usize)> = HashMap::new();
+    for (span, op) in before {
+        removed.entry(key(span, op)).or_insert((span, 0)).1 += 1;
+    }
+
+    // Group operations that appeared by span
+    let mut rewrites: Vec<Rewrite> = vec![];
+    for (span, op) in after {
+        if let Some(&mut (_, ref mut left)) = removed.get_mut(&key(span, op.clone())) {
+            if *left > 0 {
+                *left -= 1;
+                continue;
+            }
+        }
+        match rewrites.last_mut() {
+            Some(rewrite) if rewrite.span == span => rewrite.ops.push(op),
+            _ => rewrites.push(Rewrite {
+                span,
+                ops: vec![op],
+            }),
+        }
+    }
+
+    // Report removed operations not covered by a rewrite
+    let covered = |span: Span| rewrites.iter().any(|rewrite| {
+        rewrite.span.start.offset <= span.start.offset && span.end.offset <= rewrite.span.end.offset
+    });
+    let mut gone: Vec<Span> = removed.into_iter()
+        .filter(|&(_, (span, left))| left > 0 && !covered(span))
+        .map(|(_, (span, _))| span)
+        .collect();
+    gone.sort_by_key(|span| (span.start.offset, span.end.offset));
+    let mut joined: Vec<Span> = vec![];
+    for span in gone {
+        match joined.last_mut() {
+            Some(last) if span.start.offset <= last.end.offset => if span.end.offset > last.end.offset {
+                *last = last.join(span);
+            },
+            _ => joined.push(span),
+        }
+    }
+    rewrites.extend(joined.into_iter().map(|span| Rewrite {
+        span,
+        ops: vec![],
+    }));
+    rewrites.sort_by_key(|rewrite| (rewrite.span.start.offset, rewrite.span.end.offset));
+    rewrites
+}
+
+/// Describe the given operation, without its span.
+/// Routines are described without their contents.
+fn describe(op: &Op) -> String {
+    match *op {
+        Op::Routine(_, true, _) => "Loop".into(),
+        Op::Routine(_, false, _) => "Routine".into(),
+        Op::If(..) => "If".into(),
+        Op::Seek(amount, _) => format!("Seek({})", amount),
+        Op::Inc(amount, at, _) => format!("Inc({}, {})", amount, at),
+        Op::Input(at, _) => format!("Input({})", at),
+        Op::Output(at, _) => format!("Output({})", at),
+        Op::Zero(at, _) => format!("Zero({})", at),
+        Op::ZeroRange(at, len, _) => format!("ZeroRange({}, {})", at, len),
+        Op::Set(value, at, _) => format!("Set({}, {})", value, at),
+        Op::AddAndZero(ref targets, at, _) => format!("AddAndZero({:?}, {})", targets, at),
+        Op::MoveRange(first, last, distance, _) => format!("MoveRange({}, {}, {})", first, last, distance),
+        Op::MulAdd(scale, ref targets, at, _) => format!("MulAdd({}, {:?}, {})", scale, targets, at),
+        Op::DivMod(divisor, _, _) => format!("DivMod({})", divisor),
+        Op::Scan(stride, _) => format!("Scan({})", stride),
+        Op::Hang(_) => "Hang".into(),
+        Op::Print(ref bytes, _) => format!("Print({:?})", String::from_utf8_lossy(bytes)),
+        Op::Load(ref image, pointer, _) => format!("Load({} cells, {})", image.len(), pointer),
+        Op::Debug(_) => "Debug".into(),
+    }
+}
+
+/// Render a source position as JSON object.
+fn position(offset: usize, line: usize, column: usize) -> String {
+    format!("{{\"offset\":{},\"line\":{},\"column\":{}}}", offset, line, column)
+}
+
+/// Escape the given text for use in a JSON string.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+
+/// Test whether reports list what each pass rewrote, and render in both
+/// formats.
+#[test]
+fn test_report() {
+    use super::super::{Interpreter, Options};
+    use super::Pipeline;
+
+    let options = Options::default(true, false, false, false);
+    let mut program = Interpreter::interpret(",[-]>+-<[->+<].".bytes(), &options).unwrap();
+    let pipeline = Pipeline::with_passes(&["zero", "add_and_zero", "cleanup"], false).unwrap();
+    let report = pipeline.report(&mut program);
+
+    assert_eq!((report.before, report.after), (12, 4));
+    let rewrites: Vec<_> = report.passes.iter()
+        .map(|pass| {
+            let rewrites: Vec<_> = pass.rewrites.iter()
+                .map(|rewrite| (rewrite.span.start.offset, rewrite.span.end.offset, rewrite.ops.join(", ")))
+                .collect();
+            (pass.name, pass.changes, rewrites)
+        })
+        .collect();
+    assert_eq!(rewrites, vec![
+        ("zero", 1, vec![(1, 4, "Zero(0)".into())]),
+        ("add_and_zero", 1, vec![(8, 14, "AddAndZero([(1, 1)], 0)".into())]),
+        ("cleanup", 3, vec![(4, 8, "".into())]),
+    ]);
+
+    assert!(report.render(ReportFormat::Human).contains("# Pass zero: 1 changes\n#   1:2..1:5: Zero(0)\n"));
+    assert!(report.render(ReportFormat::Json).starts_with(
+        "{\"before\":12,\"after\":4,\"passes\":[{\"name\":\"zero\",\"changes\":1,\"rewrites\":[{\"start\":{\"offset\":1,",
+    ));
+    assert_eq!(escape("Print(\"a\\\n\")"), "Print(\\\"a\\\\\\n\\\")");
+}
@@ -9,23 +9,43 @@
 //! - Other cells can be at any position.
 //! - To various cells a value may be added or subtracted with a different
 //!   factor.
+//! - Operations may be in any order, the net effect of the routine on each
+//!   cell is used.
 //!
 //! This optimization therefore covers many forms of copy and multiplication
 //! routines.
 //!
+//! Memory cells wrap around, so the number of times the routine runs depends
+//! on modular arithmetic. If the base cell changes by an odd step, the
+//! routine always ends, and runs `-value * inverse(step)` times modulo 256.
+//! The factors to add with are therefore exact integers, calculated using the
+//! modular inverse of the step.
+//! If the step is even, the routine never ends for some values, and it isn't
+//! optimized.
+//!
+//! Nested routines that were optimized into zero or add and zero operations
+//! are supported as well, such as multiplying two cells. Each cell value
+//! after a run is then an affine function of the cell values before it. If
+//! the cells that are read reach a fixed point after the first run, every
+//! following run adds the same amount to the other cells. Such routines are
+//! flattened into a single multiply-accumulate operation.
+//!
 //! # Optimization requirements
-//! - There must be at least 4 operations.
-//! - Subtraction must be the first operation.
-//! - The following operations must be any number of the following sequence:
-//!     - Seek to another cell to add to or subtract from
-//!     - Add or subtract
-//! - The last operation must seek back to the base cell.
-//! - The base cell must never be touched by an other operation.
+//! - The routine must only contain seek, add or subtract, zero, set and add
+//!   and zero operations.
+//! - The seeks must add up to zero, ending at the base cell.
+//! - The base cell must change by an odd step in total, and no other cell
+//!   may depend on it.
+//! - Another cell than the base cell must be accessed.
+//! - Cells read by other cells must not change after the first run.
 //!
 //! # Example routines
 //! - `[->+<]'
 //!     - Add the current cell value to the next cell
 //!     - Zero the current cell
+//! - `[>+<-]'
+//!     - Add the current cell value to the next cell
+//!     - Zero the current cell
 //! - `[->>>+<<<]`
 //!     - Add the current cell value to the 3rd next cell
 //!     - Zero the current cell
@@ -43,13 +63,27 @@
 //!     - Add the current value to the 2nd next cell times 2
 //!     - Zero the current and 3rd cell
 //! - `[--->+<<+++>]`
-//!     - Add a 1/3e of the current cell to the next cell
+//!     - Add the current cell value times 85 (the inverse of 3) to the next
+//!       cell
 //!     - Add the current cell value to the previous cell
 //!     - Zero the current cell
+//! - `[+>->>+<+<<]`
+//!     - Add the current cell value to the 2nd next cell times 2
+//!     - Subtract the current cell value from the next cell
+//!     - Zero the current cell
+//! - `[->[->+>+<<]>[-<+>]<<]`
+//!     - Add the current cell value times the next cell to the 3rd next cell
+//!     - Zero the current cell
+//! - `[->++++++++[->+<]<]`
+//!     - Add the current cell value times 8 and the next cell to the 2nd
+//!       next cell, if the current cell isn't zero
+//!     - Zero the current and next cell, if the current cell isn't zero
+
 
 
+use std::collections::BTreeMap;
 
-use super::super::super::Op;
+use super::super::super::{Affine, Op, Span};
 
 
 
@@ -58,82 +92,293 @@ use super::super::super::Op;
 /// This optimization is applied on routines.
 /// True or false should be given to `cond` depending on whether the routine
 /// is conditional or not.
-/// The operations contained by the routine should be given to `ops`.
+/// The operations contained by the routine should be given to `ops`,
+/// the span of the routine should be given to `span`.
 ///
 /// If `Some` is returned, the whole routine should be replaced by it's
 /// contents.
-pub fn optimize_add_and_zero(cond: bool, ops: &Vec<Op>) -> Option<Op> {
-    // Do not run if this isn't a conditional loop,
-    // there must be at least six operations
-    if !cond || ops.len() < 4 {
+pub fn optimize_add_and_zero(cond: bool, ops: &[Op], span: Span) -> Option<Op> {
+    // Do not run if this isn't a conditional loop
+    if !cond {
         return None;
     }
 
-    // Create a operations iterator
-    let mut iter = ops.iter().enumerate();
+    // Find the effect of a single run on each cell, it must touch other cells
+    let mut effects = effects(ops)?;
+    if effects.keys().all(|&at| at == 0) {
+        return None;
+    }
 
-    // The first cell must subtract
-    let step;
-    match iter.next() {
-        Some((_, &Op::Inc(amount))) if amount < 0 => step = -amount,
+    // The base cell must change by an odd step,
+    // find the factor the routine runs times the cell value
+    let step = match effects.remove(&0) {
+        Some(ref effect) if effect.is_inc(0) => effect.constant,
         _ => return None,
+    };
+    if step & 1 == 0 {
+        return None;
     }
+    let scale = inverse(step).wrapping_neg();
 
-    // Create a vector for relative memory positions to copy to,
-    // and a variable that remembers the current offset
-    let mut targets = Vec::with_capacity((ops.len() - 2) / 2);
-    let mut offset = 0;
+    // Other cells must not depend on the base cell
+    if effects.values().any(|effect| effect.factor(0) != 0) {
+        return None;
+    }
 
-    // Find cells this data is moved into
-    loop {
-        // Get the seek and subtract operators
-        let (_, seek_op) = iter.next()?;
-        let (sub_i, sub_op) = iter.next()?;
+    // Routines only incrementing cells add to them with a factor
+    if effects.iter().all(|(&at, effect)| effect.is_inc(at)) {
+        let targets = effects.into_iter()
+            .map(|(at, effect)| (at, effect.constant.wrapping_mul(scale)))
+            .filter(|&(_, factor)| factor != 0)
+            .collect();
+        return Some(Op::AddAndZero(targets, 0, span));
+    }
 
-        // This must be a seek, modify the offste
-        match seek_op {
-            &Op::Seek(amount) => offset += amount,
-            _ => return None,
-        }
+    mul_add(effects, scale, span)
+}
 
-        // This must add/subtract, remember the factor to do it with
-        let factor;
-        match sub_op {
-            &Op::Inc(amount) =>
-                factor = if amount != 0 {
-                    amount as f32 / step as f32
-                } else {
-                    0f32
-                },
+/// Create a multiply-accumulate operation for a routine with the given
+/// `effects` of a single run on each cell other than the base cell.
+/// The routine runs the base cell value times `scale` times.
+///
+/// Cells are either accumulators or state. Accumulators only add values of
+/// state cells to themselves, and aren't read by other cells. State cells
+/// must reach a fixed point after the first run, so accumulators add the same
+/// amount on each following run.
+///
+/// `None` is returned if the routine doesn't have this form.
+fn mul_add(effects: BTreeMap<isize, Expr>, scale: u8, span: Span) -> Option<Op> {
+    let read = |at: isize| effects.iter()
+        .any(|(&other, effect)| other != at && effect.factor(at) != 0);
+    let (accumulators, state): (BTreeMap<_, _>, BTreeMap<_, _>) = effects.iter()
+        .map(|(&at, effect)| (at, effect.clone()))
+        .partition(|&(at, ref effect)| effect.factor(at) == 1 && !read(at));
+
+    // State must not change after the first run
+    if state.values().any(|effect| effect.substitute(&state) != *effect) {
+        return None;
+    }
+
+    // State cells are set once, accumulators add the amount of the first
+    // run, and the amount of the following runs for each run
+    let mut targets = vec![];
+    for (at, effect) in accumulators {
+        let mut delta = effect.clone();
+        delta.add(&Expr::cell(at), u8::MAX);
+        let step = delta.substitute(&state);
+        let mut base = effect;
+        base.add(&step, u8::MAX);
+        targets.push((at, base, step));
+    }
+    targets.extend(state.into_iter().map(|(at, effect)| (at, effect, Expr::constant(0))));
+    targets.sort_by_key(|&(at, _, _)| at);
+
+    let targets = targets.into_iter()
+        .filter(|&(at, ref base, ref step)| *base != Expr::cell(at) || *step != Expr::constant(0))
+        .map(|(at, base, step)| (at, base.into_affine(), step.into_affine()))
+        .collect();
+    Some(Op::MulAdd(scale, targets, 0, span))
+}
+
+/// Get the effect of a single run of the given routine operations on each
+/// cell they touch, relative to the base cell.
+///
+/// `None` is returned if the operations don't have a linear effect, or don't
+/// end at the base cell.
+fn effects(ops: &[Op]) -> Option<BTreeMap<isize, Expr>> {
+    let mut effects: BTreeMap<isize, Expr> = BTreeMap::new();
+    let mut offset = 0;
+    for op in ops {
+        match *op {
+            Op::Seek(amount, _) => offset += amount,
+            Op::Inc(amount, at, _) => {
+                let effect = effects.entry(offset + at).or_insert_with(|| Expr::cell(offset + at));
+                effect.constant = effect.constant.wrapping_add(amount as u8);
+            },
+            Op::Zero(at, _) => {
+                effects.insert(offset + at, Expr::constant(0));
+            },
+            Op::Set(value, at, _) => {
+                effects.insert(offset + at, Expr::constant(value));
+            },
+            Op::AddAndZero(ref targets, at, _) => {
+                let at = offset + at;
+                let value = effects.insert(at, Expr::constant(0))
+                    .unwrap_or_else(|| Expr::cell(at));
+                for &(target, factor) in targets {
+                    effects.entry(at + target)
+                        .or_insert_with(|| Expr::cell(at + target))
+                        .add(&value, factor);
+                }
+            },
             _ => return None,
         }
+    }
 
-        // We may not overwrite the base cell
-        if offset == 0 {
-            return None;
+    if offset == 0 {
+        Some(effects)
+    } else {
+        None
+    }
+}
+
+/// Get the modular inverse of the given odd `value`, modulo 256.
+///
+/// Each Newton iteration doubles the number of correct low bits, an odd
+/// value is its own inverse for the lowest three bits.
+fn inverse(value: u8) -> u8 {
+    let mut inverse = value;
+    for _ in 0..3 {
+        inverse = inverse.wrapping_mul(2u8.wrapping_sub(value.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+
+
+/// An affine function of the cell values before a routine runs, relative to
+/// its base cell, wrapping around.
+#[derive(Clone, PartialEq, Eq)]
+struct Expr {
+    /// The constant value.
+    constant: u8,
+
+    /// The factor to multiply each cell value with, zero factors are left
+    /// out.
+    factors: BTreeMap<isize, u8>,
+}
+
+impl Expr {
+    /// The value of the cell at the given offset.
+    fn cell(at: isize) -> Expr {
+        let mut factors = BTreeMap::new();
+        factors.insert(at, 1);
+        Expr {
+            constant: 0,
+            factors,
         }
+    }
 
-        // Add the current offset to the target list
-        if factor != 0f32 {
-            targets.push((offset, factor));
+    /// The given constant value.
+    fn constant(constant: u8) -> Expr {
+        Expr {
+            constant,
+            factors: BTreeMap::new(),
         }
+    }
+
+    /// Get the factor of the cell at the given offset.
+    fn factor(&self, at: isize) -> u8 {
+        self.factors.get(&at).cloned().unwrap_or(0)
+    }
 
-        // If the subtraction was the second last operator,
-        // the last must set the offset back to zero
-        if sub_i == ops.len() - 2 {
-            // Get the reset operator
-            let (_, reset_op) = iter.next()?;
+    /// Check whether this is the value of the cell at the given offset,
+    /// plus a constant.
+    fn is_inc(&self, at: isize) -> bool {
+        self.factors.len() == 1 && self.factor(at) == 1
+    }
 
-            // This must be addition
-            match reset_op {
-                &Op::Seek(amount) if amount == -offset=> {},
-                _ => return None,
+    /// Add the `other` function multiplied by `factor` to this one.
+    fn add(&mut self, other: &Expr, factor: u8) {
+        self.constant = self.constant.wrapping_add(other.constant.wrapping_mul(factor));
+        for (&at, &other) in &other.factors {
+            let sum = self.factor(at).wrapping_add(other.wrapping_mul(factor));
+            if sum == 0 {
+                self.factors.remove(&at);
+            } else {
+                self.factors.insert(at, sum);
             }
+        }
+    }
 
-            // This optimization is succesful, return the resulting operator
-            return Some(
-                Op::AddAndZero(targets),
-            );
+    /// Substitute the cells with the given `values`, cells without value are
+    /// kept.
+    fn substitute(&self, values: &BTreeMap<isize, Expr>) -> Expr {
+        let mut result = Expr::constant(self.constant);
+        for (&at, &factor) in &self.factors {
+            match values.get(&at) {
+                Some(value) => result.add(value, factor),
+                None => result.add(&Expr::cell(at), factor),
+            }
         }
+        result
+    }
+
+    /// Convert this function into an affine function for operations.
+    fn into_affine(self) -> Affine {
+        Affine {
+            constant: self.constant,
+            terms: self.factors.into_iter().collect(),
+        }
+    }
+}
+
+
+
+/// Test whether optimized routines give the same result as unoptimized
+/// execution with wrapping cells, for every cell value.
+#[test]
+fn test_add_and_zero() {
+    use super::super::super::{Input, Interpreter, Memory, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    let run = |options: &Options, prog: &str| {
+        let mut program = Interpreter::interpret(prog.bytes(), options).unwrap();
+        options.pipeline.run(&mut program);
+        let mut memory = Memory::new();
+        program.execute(&mut memory, options, &mut Input::new(vec![], false), &mut vec![]).unwrap();
+        memory.window(8).1.to_vec()
+    };
+
+    let routines = [
+        "[->+<]", "[--->+<<+++>]", "[+++>++<]", "[->+>++>-<<<]", "[-------<----->]",
+        "[>+<-]", "[+>-<]", "[>+<->+<]", "[>+>>-<+<<->-<]",
+        ">+++<[->[->+<]<]", ">+++<[->++++++++[->+<]<]", ">+++++<[->[->+>+<<]>[-<+>]<<]",
+        ">++>+++<<[->[->+>+<<]>[-<+>]<<]", ">+++>+<<[--->[-]>++<<]",
+    ];
+    for routine in &routines {
+        for value in 0..256 {
+            let prog = format!(">>>{}{}", "+".repeat(value), routine);
+            options.pipeline = Pipeline::level(2);
+            options.pipeline.disable("cleanup").unwrap();
+            let optimized = run(&options, &prog);
+            options.pipeline = Pipeline::level(0);
+            assert_eq!(optimized, run(&options, &prog), "{} with {}", routine, value);
+        }
+    }
+
+    // Routines are optimized regardless of the order of operations
+    options.pipeline = Pipeline::level(1);
+    let mut program = Interpreter::interpret("[>+>>-<+<<->-<]".bytes(), &options).unwrap();
+    options.pipeline.run(&mut program);
+    match program {
+        Op::Routine(ref ops, false, _) => match ops[..] {
+            [Op::AddAndZero(ref targets, 0, _)] => assert_eq!(*targets, vec![(2, 1), (3, 255)]),
+            _ => panic!("expected an add and zero operation: {:?}", ops),
+        },
+        _ => panic!("expected a top level routine"),
+    }
+
+    // Nested multiplication routines are flattened
+    let mut program = Interpreter::interpret("[->[->+>+<<]>[-<+>]<<]".bytes(), &options).unwrap();
+    options.pipeline.run(&mut program);
+    match program {
+        Op::Routine(ref ops, false, _) => match ops[..] {
+            [Op::MulAdd(1, ref targets, 0, _)] => assert_eq!(targets.len(), 3),
+            _ => panic!("expected a multiply-accumulate operation: {:?}", ops),
+        },
+        _ => panic!("expected a top level routine"),
+    }
+
+    // Routines with an even step may never end, and must not be optimized
+    let mut program = Interpreter::interpret("[-->+<]".bytes(), &options).unwrap();
+    options.pipeline.run(&mut program);
+    match program {
+        Op::Routine(ref ops, false, _) => match ops[..] {
+            [Op::Routine(_, true, _)] => {},
+            _ => panic!("expected an unoptimized routine"),
+        },
+        _ => panic!("expected a top level routine"),
     }
+    assert_eq!(inverse(3).wrapping_mul(3), 1);
 }
@@ -1,6 +1,8 @@
 mod add_and_zero;
+mod scan;
 mod zero;
 
 // Reexport
 pub use self::add_and_zero::optimize_add_and_zero;
-pub use self::zero::optimize_zero;
+pub use self::scan::optimize_scan;
+pub use self::zero::{optimize_zero, optimize_zero_strict};
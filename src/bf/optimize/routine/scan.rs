@@ -0,0 +1,108 @@
+//! # Scan routine optimization
+//! This optimizes routines that only seek the memory pointer until a zero
+//! cell is found, and replaces the routine with a single instruction.
+//!
+//! Such routines are commonly used to navigate data structures. The
+//! instruction searches memory for a zero cell directly, instead of seeking
+//! once for each iteration.
+//!
+//! # Optimization requirements
+//! - A routine that must only contain a single seek operation.
+//!
+//! # Example routines
+//! - `[>]`
+//!     - Seek up to the next zero cell
+//! - `[<]`
+//!     - Seek down to the previous zero cell
+//! - `[>>>]`
+//!     - Seek up to the next zero cell, in steps of 3
+
+
+
+use super::super::super::{Op, Span};
+
+
+
+/// Optimize scanning routines.
+///
+/// This optimization is applied on routines.
+/// True or false should be given to `cond` depending on whether the routine
+/// is conditional or not.
+/// The operations contained by the routine should be given to `ops`,
+/// the span of the routine should be given to `span`.
+///
+/// If `Some` is returned, the whole routine should be replaced by it's
+/// contents.
+pub fn optimize_scan(cond: bool, ops: &[Op], span: Span) -> Option<Op> {
+    // Do not run if this isn't a conditional loop
+    if !cond {
+        return None;
+    }
+
+    match ops[..] {
+        [Op::Seek(stride, _)] if stride != 0 => Some(Op::Scan(stride, span)),
+        _ => None,
+    }
+}
+
+
+
+/// Test whether scanning routines are optimized, and find the right cell.
+#[test]
+fn test_scan() {
+    use super::super::super::{Interpreter, Memory, Options, Pipeline};
+
+    let mut options = Options::default(true, false, false, false);
+    options.pipeline = Pipeline::with_passes(&["scan"], false).unwrap();
+    let mut program = Interpreter::interpret("[>>][<]".bytes(), &options).unwrap();
+    options.pipeline.run(&mut program);
+    match program {
+        Op::Routine(ref ops, false, _) => match ops[..] {
+            [Op::Scan(2, _), Op::Scan(-1, _)] => {},
+            _ => panic!("expected scan operations: {:?}", ops),
+        },
+        _ => panic!("expected a top level routine"),
+    }
+
+    let mut memory = Memory::new();
+    memory.write(1, 1);
+    memory.write(2, 1);
+    memory.write(4, 1);
+    memory.scan(1);
+    assert_eq!(memory.pointer(), 0);
+    memory.seek(1);
+    memory.scan(1);
+    assert_eq!(memory.pointer(), 3);
+    memory.seek(1);
+    memory.scan(2);
+    assert_eq!(memory.pointer(), 6);
+    memory.seek(-2);
+    memory.scan(-3);
+    assert_eq!(memory.pointer(), 0);
+    memory.seek(4);
+    memory.scan(-1);
+    assert_eq!(memory.pointer(), 3);
+    memory.seek(-1);
+    memory.scan(-1);
+    assert_eq!(memory.pointer(), 0);
+}
+
+/// Test whether scanning down past the first cell is reported as a hang, as
+/// it would never end.
+#[test]
+fn test_scan_start() {
+    use super::super::super::{bf, Error, Memory, Options, RuntimeError};
+
+    let mut memory = Memory::new();
+    memory.write(0, 1);
+    memory.write(2, 1);
+    memory.seek(2);
+    assert!(!memory.scan(-2));
+    assert_eq!(memory.pointer(), 2);
+
+    let options = Options::default(true, false, false, false);
+    match bf(&mut "+[<]".as_bytes(), &options) {
+        Err(Error::Runtime(RuntimeError::Hang(_))) => {},
+        result => panic!("expected a hang: {:?}", result),
+    }
+}
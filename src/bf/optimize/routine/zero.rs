@@ -10,6 +10,11 @@
 //! routine as zero would never be reached. This optimization ignores this
 //! and zero's te cell.
 //!
+//! The strict variant preserves these semantics. It only zeroes the cell if
+//! the routine adds an odd number, which always reaches zero. Routines adding
+//! nothing are replaced with an operation reporting the program hangs, other
+//! routines are left alone.
+//!
 //! # Optimization requirements
 //! - A routine that must only contain add and subtract operations.
 //!
@@ -22,10 +27,16 @@
 //!     - Zero the current cell
 //! - `[++-]`
 //!     - Zero the current cell
+//! - `[+-]`
+//!     - Zero the current cell
+//!     - Report the program hangs if not zero, if strict
+//! - `[++]`
+//!     - Zero the current cell
+//!     - Keep the routine, if strict
 
 
 
-use super::super::super::Op;
+use super::super::super::{Op, Span};
 
 
 
@@ -34,25 +45,69 @@ use super::super::super::Op;
 /// This optimization is applied on routines.
 /// True or false should be given to `cond` depending on whether the routine
 /// is conditional or not.
-/// The operations contained by the routine should be given to `ops`.
+/// The operations contained by the routine should be given to `ops`,
+/// the span of the routine should be given to `span`.
 ///
 /// If `Some` is returned, the whole routine should be replaced by it's
 /// contents.
-pub fn optimize_zero(cond: bool, ops: &Vec<Op>) -> Option<Op> {
+pub fn optimize_zero(cond: bool, ops: &[Op], span: Span) -> Option<Op> {
     // Do not run if this isn't a conditional loop
     if !cond {
         return None;
     }
 
     // Check whether we can optimize
-    if ops.iter().all(
-        |op| match *op {
-            Op::Inc(..) => true,
-            _ => false,
-        }
-    ) {
-        Some(Op::Zero)
+    if ops.iter().all(|op| matches!(*op, Op::Inc(_, 0, _))) {
+        Some(Op::Zero(0, span))
     } else {
         None
     }
 }
+
+/// Optimize zeroing routines, preserving the semantics of routines that
+/// never end.
+///
+/// See `optimize_zero` for details.
+pub fn optimize_zero_strict(cond: bool, ops: &[Op], span: Span) -> Option<Op> {
+    // Do not run if this isn't a conditional loop
+    if !cond {
+        return None;
+    }
+
+    // Sum the increments, all operations must be increments
+    let mut total: isize = 0;
+    for op in ops {
+        match *op {
+            Op::Inc(amount, 0, _) => total += amount,
+            _ => return None,
+        }
+    }
+
+    // Odd amounts always reach zero, zero amounts never end
+    match total.rem_euclid(256) {
+        0 => Some(Op::Hang(span)),
+        total if total % 2 == 1 => Some(Op::Zero(0, span)),
+        _ => None,
+    }
+}
+
+
+
+/// Test whether the strict zeroing optimization preserves loops that never
+/// end.
+#[test]
+fn test_zero_strict() {
+    use super::super::super::{bf, Error, Options, RuntimeError};
+
+    let mut options = Options::default(true, false, false, false);
+    assert_eq!(bf(&mut "+++[+-]++[++].".as_bytes(), &options).unwrap(), "\u{0}");
+
+    options.pipeline.strict();
+    assert_eq!(bf(&mut "+++[---]++++[--].".as_bytes(), &options).unwrap(), "\u{0}");
+    assert_eq!(bf(&mut "[+-]+".as_bytes(), &options).unwrap(), "");
+    match bf(&mut "+\n[+-]".as_bytes(), &options) {
+        Err(Error::Runtime(RuntimeError::Hang(position))) =>
+            assert_eq!((position.line, position.column), (2, 1)),
+        _ => panic!("expected the program to hang"),
+    }
+}
@@ -1,5 +1,7 @@
 use super::tty_read::ReaderOptions;
 
+use super::{Dialect, Pipeline, ReportFormat};
+
 
 
 /// An options object, that defines how the brainfuck interpreter is used.
@@ -18,6 +20,26 @@ pub struct Options {
 
     /// Prettify described program logic.
     pub pretty: bool,
+
+    /// The dialect the program source is written in.
+    pub dialect: Dialect,
+
+    /// Interpret the `#` debug token, to dump memory state to stderr.
+    pub debug: bool,
+
+    /// Use everything after the first `!` in the program as program input.
+    pub inline_input: bool,
+
+    /// Read input from the terminal when inline input is exhausted.
+    /// If `false`, zero is read instead.
+    pub input_fallback: bool,
+
+    /// The optimization passes to run on interpreted programs.
+    pub pipeline: Pipeline,
+
+    /// Report what each optimization pass did to stderr, in the given
+    /// format.
+    pub opt_report: Option<ReportFormat>,
 }
 
 impl Options {
@@ -34,6 +56,12 @@ impl Options {
             profile,
             describe,
             pretty,
+            dialect: Dialect::Brainfuck,
+            debug: false,
+            inline_input: false,
+            input_fallback: true,
+            pipeline: Pipeline::level(2),
+            opt_report: None,
         }
     }
 }
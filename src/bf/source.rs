@@ -0,0 +1,171 @@
+use std::fmt;
+use std::iter::Chain;
+use std::vec;
+
+
+
+/// A position in a brainfuck program source.
+///
+/// The `offset` is the zero based byte offset in the source.
+/// The `line` and `column` are one based, and are counted in bytes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset in the source.
+    pub offset: usize,
+
+    /// Line number, starting at one.
+    pub line: usize,
+
+    /// Column number in bytes, starting at one.
+    pub column: usize,
+}
+
+impl Position {
+    /// Create a position at the start of a source.
+    pub fn start() -> Position {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Advance this position past the given `byte`.
+    fn advance(&mut self, byte: u8) {
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+
+
+/// A range in a brainfuck program source.
+///
+/// The `start` position is inclusive, the `end` position is exclusive.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// The position of the first byte in this range.
+    pub start: Position,
+
+    /// The position just after the last byte in this range.
+    pub end: Position,
+}
+
+impl Span {
+    /// Create a new span from `start` up to `end`.
+    pub fn new(start: Position, end: Position) -> Span {
+        Span {
+            start,
+            end,
+        }
+    }
+
+    /// Create a span covering this span and the given `other` span that
+    /// follows it, including anything in between.
+    pub fn join(&self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}..{:?}", self.start, self.end)
+    }
+}
+
+
+
+/// A brainfuck program source.
+///
+/// This wraps a stream of program bytes, and keeps track of the span
+/// of each byte that is read from it.
+///
+/// A shebang line at the start of the source, such as
+/// `#!/usr/bin/env brainfuck-rs-quick`, is skipped. This allows programs to
+/// be executable scripts.
+///
+/// If inline input is enabled, the source ends at the first `!` byte.
+/// Everything after it is considered to be program input, and is left
+/// unread in the stream.
+pub struct Source<I> {
+    /// The stream of program bytes, after the bytes that were read ahead to
+    /// find a shebang line.
+    bytes: Chain<vec::IntoIter<u8>, I>,
+
+    /// The position of the next byte in the stream.
+    position: Position,
+
+    /// Whether to end the source at the first `!` byte.
+    inline_input: bool,
+
+    /// Whether the end of the source has been reached.
+    ended: bool,
+}
+
+impl<I: Iterator<Item = u8>> Source<I> {
+    /// Create a new source for the given stream of program bytes.
+    ///
+    /// If `inline_input` is `true`, the source ends at the first `!` byte.
+    pub fn new(mut bytes: I, inline_input: bool) -> Source<I> {
+        let mut position = Position::start();
+
+        // Skip a shebang line, keep the bytes read ahead otherwise
+        let ahead: Vec<u8> = bytes.by_ref().take(2).collect();
+        let ahead = if ahead == b"#!" {
+            for byte in ahead.into_iter().chain(bytes.by_ref()) {
+                position.advance(byte);
+                if byte == b'\n' {
+                    break;
+                }
+            }
+            vec![]
+        } else {
+            ahead
+        };
+
+        Source {
+            bytes: ahead.into_iter().chain(bytes),
+            position,
+            inline_input,
+            ended: false,
+        }
+    }
+
+    /// Get the position of the next byte in the stream.
+    /// If the stream is empty, this is the end position of the source.
+    pub fn current(&self) -> Position {
+        self.position
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Source<I> {
+    type Item = (u8, Span);
+
+    /// Read the next byte from the stream, along with it's span.
+    fn next(&mut self) -> Option<(u8, Span)> {
+        // Do not read past the inline input separator
+        if self.ended {
+            return None;
+        }
+        let byte = self.bytes.next()?;
+        if self.inline_input && byte == b'!' {
+            self.ended = true;
+            return None;
+        }
+
+        let start = self.position;
+        self.position.advance(byte);
+        Some((byte, Span::new(start, self.position)))
+    }
+}
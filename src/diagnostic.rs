@@ -0,0 +1,159 @@
+use bf::{ParseError, Position, RuntimeError};
+
+
+
+/// A compiler style diagnostic, pointing at a position in a program source.
+///
+/// The diagnostic is rendered with an excerpt of the source line, and a
+/// caret pointing at the relevant column.
+/// Columns are counted in bytes, as program source isn't required to be
+/// valid UTF-8. The caret is aligned to the characters before the column.
+pub struct Diagnostic<'a> {
+    /// The diagnostic level, such as `error` or `warning`.
+    level: &'a str,
+
+    /// The main diagnostic message.
+    message: String,
+
+    /// The position the diagnostic points at.
+    position: Position,
+
+    /// An optional label, shown next to the caret.
+    label: Option<String>,
+
+    /// Additional notes, shown below the source excerpt.
+    notes: Vec<String>,
+}
+
+impl<'a> Diagnostic<'a> {
+    /// Create a new diagnostic with the given `level` and `message`,
+    /// pointing at `position`.
+    pub fn new(level: &'a str, message: String, position: Position) -> Diagnostic<'a> {
+        Diagnostic {
+            level,
+            message,
+            position,
+            label: None,
+            notes: vec![],
+        }
+    }
+
+    /// Create an error diagnostic for the given parse error.
+    pub fn parse_error(err: &ParseError) -> Diagnostic<'static> {
+        let diagnostic = Diagnostic::new("error", err.message(), err.position());
+
+        // Point at the unclosed loop, and note where the program ended
+        match *err {
+            ParseError::UnmatchedClose(_) =>
+                diagnostic.label("no matching '[' before this"),
+            ParseError::Unclosed(_, end) =>
+                diagnostic.label("this loop is never closed")
+                    .note(format!(
+                        "expected ']' before the end of the program at {}:{}",
+                        end.line,
+                        end.column,
+                    )),
+        }
+    }
+
+    /// Create an error diagnostic for the given runtime error.
+    pub fn runtime_error(err: &RuntimeError) -> Diagnostic<'static> {
+        let diagnostic = Diagnostic::new("error", err.message(), err.position());
+
+        match *err {
+            RuntimeError::Hang(_) =>
+                diagnostic.label("this loop doesn't change the current cell")
+                    .note("the loop was entered with a non-zero cell value"),
+            RuntimeError::OutOfMemory(_) =>
+                diagnostic.label("this accesses a cell past the end of memory"),
+        }
+    }
+
+    /// Set the label shown next to the caret.
+    pub fn label<S: Into<String>>(mut self, label: S) -> Diagnostic<'a> {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Add a note, shown below the source excerpt.
+    pub fn note<S: Into<String>>(mut self, note: S) -> Diagnostic<'a> {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Render this diagnostic for the program at `path` with the given
+    /// `source`. If the source line isn't available, the excerpt is omitted.
+    pub fn render(&self, path: &str, source: &[u8]) -> String {
+        let number = self.position.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let mut out = format!(
+            "{}: {}\n{}--> {}:{}:{}",
+            self.level,
+            self.message,
+            gutter,
+            path,
+            self.position.line,
+            self.position.column,
+        );
+
+        // Find the source line the position is on, show an excerpt
+        let line = source.split(|&b| b == b'\n')
+            .nth(self.position.line - 1)
+            .filter(|_| !source.is_empty());
+        if let Some(bytes) = line {
+            let line = String::from_utf8_lossy(bytes);
+            let line = line.trim_end_matches('\r');
+
+            // Pad the caret to the byte column, keeping tabs to stay aligned
+            let prefix = &bytes[..(self.position.column - 1).min(bytes.len())];
+            let padding: String = String::from_utf8_lossy(prefix)
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            out += &format!(
+                "\n{} |\n{} | {}\n{} | {}^",
+                gutter,
+                number,
+                line,
+                gutter,
+                padding,
+            );
+            if let Some(ref label) = self.label {
+                out += &format!(" {}", label);
+            }
+        } else if let Some(ref label) = self.label {
+            out += &format!("\n{} = {}", gutter, label);
+        }
+
+        for note in &self.notes {
+            out += &format!("\n{} = note: {}", gutter, note);
+        }
+
+        out
+    }
+
+    /// Print this diagnostic to stderr, for the program at `path` with the
+    /// given `source`.
+    pub fn emit(&self, path: &str, source: &[u8]) {
+        eprintln!("{}\n", self.render(path, source));
+    }
+}
+
+
+
+/// Test whether the caret points at the byte column, on lines with
+/// multi-byte characters.
+#[test]
+fn test_caret_column() {
+    use bf::{bf, Error, Options};
+
+    let source = "Grüße ]\n";
+    let err = match bf(&mut source.as_bytes(), &Options::default(true, false, false, false)) {
+        Err(Error::Parse(err)) => err,
+        _ => panic!("expected a parse error"),
+    };
+    assert_eq!(err.position().column, 9);
+    let rendered = Diagnostic::parse_error(&err).render("prog.b", source.as_bytes());
+    assert!(rendered.contains("1 | Grüße ]\n  |       ^"));
+}
@@ -1,14 +1,17 @@
 mod app;
 mod arg_handler;
 mod bf;
+mod diagnostic;
 mod profiler;
 
-use std::fs::File;
-use std::io::Read;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::process;
 
 use arg_handler::ArgHandler;
-use bf::{bf, Options};
-use profiler::Profiler;
+use bf::{bf, lint, Error, Interpreter, LintCode, Options};
+use diagnostic::Diagnostic;
 
 
 
@@ -18,11 +21,31 @@ fn main() {
     let arg_handler = ArgHandler::parse();
     let options = arg_handler.as_options();
 
-    // Read the file
-    let program = read_file(arg_handler.file(), &options);
+    // Check the program instead if linting
+    if let Some((path, allowed)) = arg_handler.lint() {
+        lint_file(path, &allowed, &options);
+        return;
+    }
+
+    // Open the file
+    let path = arg_handler.file();
+    let mut program = open_file(path);
+
+    // Run the program, report parse and runtime errors
+    let output = match bf(&mut program, &options) {
+        Ok(output) => output,
+        Err(err) => {
+            let diagnostic = match err {
+                Error::Parse(ref err) => Diagnostic::parse_error(err),
+                Error::Runtime(ref err) => Diagnostic::runtime_error(err),
 
-    // Run the program
-    let output = bf(&program, &options);
+                // Read errors have no position to point at
+                Error::Io(_) => exit_error(err),
+            };
+            diagnostic.emit(path, &read_excerpt_source(path));
+            process::exit(1);
+        },
+    };
 
     // Print the output
     if arg_handler.buffer() {
@@ -30,24 +53,82 @@ fn main() {
     }
 }
 
-/// Read file contents.
-fn read_file(path: &str, options: &Options) -> String {
-    // Profile
-    let mut profiler = Profiler::new(options.profile);
+/// Report the given error, and exit the application.
+fn exit_error<E: Display>(err: E) -> ! {
+    eprintln!("error: {}", err);
+    process::exit(1);
+}
 
-    // Open the input file
-    let mut file = File::open(path)
-        .expect("failed to open 'program.bf'");
+/// Open the program file at the given `path` for streaming.
+/// If the path is `-`, the program is read from stdin.
+///
+/// If the file could not be opened, an error is reported and the
+/// application exits.
+fn open_file(path: &str) -> BufReader<Box<dyn Read>> {
+    let reader: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(
+            File::open(path)
+                .unwrap_or_else(|err| exit_error(format!("failed to open program file: {}", err))),
+        )
+    };
+    BufReader::new(reader)
+}
 
-    // Read the file
-    let mut program = String::new();
-    file.read_to_string(&mut program)
-        .expect("failed to read 'program.bf'");
+/// Check the program file at the given `path` for problems, and report
+/// warnings for all lints that aren't `allowed`.
+fn lint_file(path: &str, allowed: &[LintCode], options: &Options) {
+    // Read the program
+    let mut source = vec![];
+    if let Err(err) = open_file(path).read_to_end(&mut source) {
+        exit_error(Error::from(err));
+    }
 
-    // Report time
-    if options.profile {
-        profiler.report("Loading program");
+    // Interpret the program without optimizations, report parse errors
+    let program = match Interpreter::interpret(source.iter().cloned(), options) {
+        Ok(program) => program,
+        Err(err) => {
+            Diagnostic::parse_error(&err).emit(path, &source);
+            process::exit(1);
+        },
+    };
+
+    // Report all lints that aren't allowed, explain each lint once
+    let lints: Vec<_> = lint(&program)
+        .into_iter()
+        .filter(|lint| !allowed.contains(&lint.code))
+        .collect();
+    let mut explained = vec![];
+    for lint in &lints {
+        let level = format!("warning[{}]", lint.code.code());
+        let mut diagnostic = Diagnostic::new(&level, lint.message.clone(), lint.span.start)
+            .label(lint.code.name());
+        if !explained.contains(&lint.code) {
+            explained.push(lint.code);
+            diagnostic = diagnostic.note(format!(
+                "use `--allow {}` to suppress this warning",
+                lint.code.code(),
+            ));
+        }
+        diagnostic.emit(path, &source);
     }
 
-    program
+    if !lints.is_empty() {
+        eprintln!(
+            "warning: {} warning{} emitted",
+            lints.len(),
+            if lints.len() == 1 { "" } else { "s" },
+        );
+    }
+}
+
+/// Read the program file at the given `path` again, to show source excerpts
+/// in diagnostics. If the program was read from stdin, it can't be read
+/// again, and no source is returned.
+fn read_excerpt_source(path: &str) -> Vec<u8> {
+    if path == "-" {
+        return vec![];
+    }
+    fs::read(path).unwrap_or_default()
 }